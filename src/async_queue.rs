@@ -0,0 +1,353 @@
+// Async adapters over the queues, a Sink to push to the back and a Stream to pop from the front.
+// Pending polls register wakers, woken by pops for sinks waiting for room and by pushes for
+// streams waiting for items
+use crate::list::LinkedRingBufferList;
+use crate::ring_buffer::{RingBuffer, Slots};
+use futures_core::Stream;
+use futures_sink::Sink;
+use parking_lot::Mutex;
+use std::{
+    mem,
+    pin::Pin,
+    sync::{
+        atomic::{fence, AtomicUsize, Ordering::*},
+        Arc,
+    },
+    task::{Context, Poll, Waker},
+};
+
+pub trait Queue {
+    type Item;
+
+    fn push(&self, item: Self::Item) -> Result<(), Self::Item>;
+    fn pop(&self) -> Option<Self::Item>;
+}
+
+impl<T, const N: usize, S: Slots<T>> Queue for RingBuffer<T, N, S> {
+    type Item = T;
+
+    fn push(&self, item: T) -> Result<(), T> {
+        self.push_back(item).map(|_| ())
+    }
+
+    fn pop(&self) -> Option<T> {
+        self.pop_front()
+    }
+}
+
+// Never full, sinks are always ready
+impl<T, const N: usize> Queue for LinkedRingBufferList<T, N> {
+    type Item = T;
+
+    fn push(&self, item: T) -> Result<(), T> {
+        self.push_back(item);
+        Ok(())
+    }
+
+    fn pop(&self) -> Option<T> {
+        self.pop_front()
+    }
+}
+
+// The stream ends once all sinks are closed or dropped and the queue is drained. Sinks fail once
+// all streams are dropped
+pub fn split<Q: Queue>(queue: Q) -> (QueueSink<Q>, QueueStream<Q>) {
+    let shared = Arc::new(Shared {
+        queue,
+        sinks: AtomicUsize::new(1),
+        streams: AtomicUsize::new(1),
+        pushed: Wakers::new(),
+        popped: Wakers::new(),
+    });
+    (
+        QueueSink {
+            shared: shared.clone(),
+            pending: None,
+            closed: false,
+        },
+        QueueStream { shared },
+    )
+}
+
+struct Shared<Q> {
+    queue: Q,
+    sinks: AtomicUsize,
+    streams: AtomicUsize,
+    // Streams waiting for items
+    pushed: Wakers,
+    // Sinks waiting for room
+    popped: Wakers,
+}
+
+struct Wakers {
+    wakers: Mutex<Vec<Waker>>,
+    // Lets pushes and pops skip the lock when no one is waiting
+    count: AtomicUsize,
+}
+
+pub struct QueueSink<Q: Queue> {
+    shared: Arc<Shared<Q>>,
+    // Sent while the queue was full, pushed on flush
+    pending: Option<Q::Item>,
+    closed: bool,
+}
+
+pub struct QueueStream<Q> {
+    shared: Arc<Shared<Q>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl<Q: Queue> Shared<Q> {
+    fn push(&self, item: Q::Item) -> Result<(), Q::Item> {
+        self.queue.push(item)?;
+        self.pushed.wake_all();
+        Ok(())
+    }
+
+    fn pop(&self) -> Option<Q::Item> {
+        let item = self.queue.pop()?;
+        self.popped.wake_all();
+        Some(item)
+    }
+}
+
+impl Wakers {
+    fn new() -> Self {
+        Self {
+            wakers: Mutex::new(vec![]),
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    // Callers shall retry after registering, wakes from then on are not missed
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+        self.count.store(wakers.len(), SeqCst);
+        fence(SeqCst);
+    }
+
+    fn wake_all(&self) {
+        fence(SeqCst);
+        if self.count.load(SeqCst) == 0 {
+            return;
+        }
+        let wakers = {
+            let mut wakers = self.wakers.lock();
+            self.count.store(0, SeqCst);
+            mem::take(&mut *wakers)
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl<Q: Queue> QueueSink<Q> {
+    fn release(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            if self.shared.sinks.fetch_sub(1, AcqRel) == 1 {
+                self.shared.pushed.wake_all();
+            }
+        }
+    }
+}
+
+impl<Q: Queue> Sink<Q::Item> for QueueSink<Q> {
+    type Error = Disconnected;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Disconnected>> {
+        self.poll_flush(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Q::Item) -> Result<(), Disconnected> {
+        let this = self.get_mut();
+        debug_assert!(
+            this.pending.is_none(),
+            "start_send before the sink is ready"
+        );
+        if this.closed || this.shared.streams.load(Acquire) == 0 {
+            return Err(Disconnected);
+        }
+        if let Err(item) = this.shared.push(item) {
+            this.pending = Some(item);
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Disconnected>> {
+        let this = self.get_mut();
+        let item = match this.pending.take() {
+            Some(item) => item,
+            None => return Poll::Ready(Ok(())),
+        };
+        let item = match this.shared.push(item) {
+            Ok(()) => return Poll::Ready(Ok(())),
+            Err(item) => item,
+        };
+        this.shared.popped.register(cx.waker());
+        if this.shared.streams.load(Acquire) == 0 {
+            return Poll::Ready(Err(Disconnected));
+        }
+        match this.shared.push(item) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(item) => {
+                this.pending = Some(item);
+                Poll::Pending
+            }
+        }
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Disconnected>> {
+        let flushed = self.as_mut().poll_flush(cx);
+        if flushed.is_ready() {
+            self.get_mut().release();
+        }
+        flushed
+    }
+}
+
+impl<Q: Queue> Stream for QueueStream<Q> {
+    type Item = Q::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Q::Item>> {
+        let shared = &self.shared;
+        if let Some(item) = shared.pop() {
+            return Poll::Ready(Some(item));
+        }
+        shared.pushed.register(cx.waker());
+        match shared.pop() {
+            Some(item) => Poll::Ready(Some(item)),
+            // Pushed right before the last sink closed
+            None if shared.sinks.load(Acquire) == 0 => Poll::Ready(shared.pop()),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<Q: Queue> Clone for QueueSink<Q> {
+    fn clone(&self) -> Self {
+        self.shared.sinks.fetch_add(1, Relaxed);
+        Self {
+            shared: self.shared.clone(),
+            pending: None,
+            closed: false,
+        }
+    }
+}
+
+impl<Q> Clone for QueueStream<Q> {
+    fn clone(&self) -> Self {
+        self.shared.streams.fetch_add(1, Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Q: Queue> Drop for QueueSink<Q> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+impl<Q> Drop for QueueStream<Q> {
+    fn drop(&mut self) {
+        if self.shared.streams.fetch_sub(1, AcqRel) == 1 {
+            self.shared.popped.wake_all();
+        }
+    }
+}
+
+// The pending item is never pinned
+impl<Q: Queue> Unpin for QueueSink<Q> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{
+        executor::{block_on, LocalPool},
+        task::LocalSpawnExt,
+        SinkExt, StreamExt,
+    };
+    use itertools::Itertools;
+    use std::{cell::RefCell, rc::Rc, thread};
+
+    #[test]
+    pub fn backpressure() {
+        // Holds 3 items, the sender waits for the receiver to make room
+        let (mut sink, stream) = split(RingBuffer::<usize, 4>::new());
+        let mut pool = LocalPool::new();
+        let received = Rc::new(RefCell::new(vec![]));
+        let received_clone = received.clone();
+        pool.spawner()
+            .spawn_local(async move {
+                for i in 0..100 {
+                    sink.send(i).await.unwrap();
+                }
+            })
+            .unwrap();
+        pool.spawner()
+            .spawn_local(async move {
+                *received_clone.borrow_mut() = stream.collect::<Vec<_>>().await;
+            })
+            .unwrap();
+        pool.run();
+        assert_eq!(*received.borrow(), (0..100).collect_vec());
+    }
+
+    #[test]
+    pub fn disconnect() {
+        let (mut sink, stream) = split(RingBuffer::<usize, 2>::new());
+        block_on(sink.send(1)).unwrap();
+        drop(stream);
+        assert_eq!(block_on(sink.send(2)), Err(Disconnected));
+
+        let (sink, mut stream) = split(LinkedRingBufferList::<usize, 4>::new());
+        let mut sink2 = sink.clone();
+        drop(sink);
+        block_on(sink2.send(1)).unwrap();
+        block_on(sink2.close()).unwrap();
+        assert_eq!(block_on(stream.next()), Some(1));
+        assert_eq!(block_on(stream.next()), None);
+    }
+
+    #[test]
+    pub fn across_threads() {
+        let num = 20480;
+        let (sink, stream) = split(RingBuffer::<usize, 64>::new());
+        let senders = (0..4)
+            .map(|t| {
+                let mut sink = sink.clone();
+                thread::spawn(move || {
+                    block_on(async {
+                        for i in 0..num {
+                            sink.send(t * num + i).await.unwrap();
+                        }
+                    })
+                })
+            })
+            .collect_vec();
+        drop(sink);
+        let receivers = (0..2)
+            .map(|_| {
+                let stream = stream.clone();
+                thread::spawn(move || block_on(stream.collect::<Vec<_>>()))
+            })
+            .collect_vec();
+        drop(stream);
+        senders.into_iter().for_each(|t| t.join().unwrap());
+        let mut all = receivers
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect_vec();
+        all.sort();
+        assert_eq!(all, (0..4 * num).collect_vec());
+    }
+}
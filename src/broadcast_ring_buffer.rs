@@ -0,0 +1,347 @@
+// A ring buffer fanning out one stream of events to any number of subscribers, each of which sees
+// every event. The publisher writes at its own sequence and subscribers read behind it at their
+// own cursors. The publisher is held back by the slowest subscriber, or in lossy mode runs over
+// them, and subscribers lapped find out by how many events they missed.
+// Slots are laid out as in RingBuffer, with a word for each flag. A flag keeps the sequence of the event in its slot, plus one
+// so zero is never written, in the high bits and the number of subscribers cloning the event in
+// the low bits. The publisher shall wait for them to finish before overwriting
+use crossbeam_utils::{Backoff, CachePadded};
+use parking_lot::Mutex;
+use std::{
+    mem::MaybeUninit,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering::*},
+        Arc,
+    },
+};
+
+const READER_BITS: usize = 16;
+const READER_MASK: usize = (1 << READER_BITS) - 1;
+// In place of the reader count while the slot is being written
+const WRITING: usize = READER_MASK;
+
+pub struct BroadcastRingBuffer<T> {
+    tail: CachePadded<AtomicUsize>,
+    elements: Box<[MaybeUninit<T>]>,
+    flags: Box<[AtomicUsize]>,
+    cursors: Mutex<Vec<Arc<CachePadded<AtomicUsize>>>>,
+    lossy: bool,
+    closed: AtomicBool,
+}
+
+pub struct Publisher<T> {
+    buffer: Arc<BroadcastRingBuffer<T>>,
+    seq: usize,
+    // Slowest cursor from last time, only ever behind the real one
+    gate: usize,
+}
+
+pub struct Subscriber<T> {
+    buffer: Arc<BroadcastRingBuffer<T>>,
+    cursor: Arc<CachePadded<AtomicUsize>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    // Overwritten before read, the cursor has moved to the oldest event left
+    Lagged(usize),
+    Disconnected,
+}
+
+impl<T> BroadcastRingBuffer<T> {
+    // Holds up to `cap` events not yet seen by all subscribers
+    pub fn with_capacity(cap: usize) -> Self {
+        Self::new(cap, false)
+    }
+
+    // Holds the last `cap` events, the publisher never waits for subscribers
+    pub fn lossy(cap: usize) -> Self {
+        Self::new(cap, true)
+    }
+
+    fn new(cap: usize, lossy: bool) -> Self {
+        assert!(cap > 0, "capacity should be positive");
+        Self {
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            elements: (0..cap).map(|_| MaybeUninit::uninit()).collect(),
+            flags: (0..cap).map(|_| AtomicUsize::new(0)).collect(),
+            cursors: Mutex::new(vec![]),
+            lossy,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn split(self) -> (Publisher<T>, Subscriber<T>) {
+        let publisher = Publisher {
+            buffer: Arc::new(self),
+            seq: 0,
+            gate: 0,
+        };
+        let subscriber = publisher.subscribe();
+        (publisher, subscriber)
+    }
+
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.elements.len()
+    }
+
+    fn join(self: &Arc<Self>, cursor: usize) -> Subscriber<T> {
+        let cursor = Arc::new(CachePadded::new(AtomicUsize::new(cursor)));
+        self.cursors.lock().push(cursor.clone());
+        Subscriber {
+            buffer: self.clone(),
+            cursor,
+        }
+    }
+
+    fn slowest(&self, seq: usize) -> usize {
+        self.cursors
+            .lock()
+            .iter()
+            .map(|cursor| cursor.load(Acquire))
+            .min()
+            .unwrap_or(seq)
+    }
+}
+
+impl<T> Publisher<T> {
+    // Fails when the slowest subscriber is a full lap behind, never in lossy mode
+    pub fn publish(&mut self, data: T) -> Result<(), T> {
+        let buffer = &*self.buffer;
+        let cap = buffer.capacity();
+        if !buffer.lossy && self.seq >= self.gate + cap {
+            self.gate = buffer.slowest(self.seq);
+            if self.seq >= self.gate + cap {
+                return Err(data);
+            }
+        }
+        let idx = self.seq % cap;
+        let flag = &buffer.flags[idx];
+        let backoff = Backoff::new();
+        let flag_val = loop {
+            let flag_val = flag.load(Acquire);
+            if flag_val & READER_MASK == 0
+                && flag
+                    .compare_exchange(flag_val, flag_val | WRITING, AcqRel, Acquire)
+                    .is_ok()
+            {
+                break flag_val;
+            }
+            // Only subscribers running over the event there are in the way
+            backoff.snooze();
+        };
+        let ele = buffer.elements[idx].as_ptr() as *mut T;
+        unsafe {
+            if flag_val != 0 {
+                ptr::drop_in_place(ele);
+            }
+            ptr::write(ele, data);
+        }
+        self.seq += 1;
+        flag.store(self.seq << READER_BITS, Release);
+        buffer.tail.store(self.seq, Release);
+        Ok(())
+    }
+
+    // Sees the events published from now on
+    pub fn subscribe(&self) -> Subscriber<T> {
+        self.buffer.join(self.seq)
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.buffer.cursors.lock().len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+}
+
+impl<T: Clone> Subscriber<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let buffer = &*self.buffer;
+        let cap = buffer.capacity();
+        let cursor = self.cursor.load(Relaxed);
+        let backoff = Backoff::new();
+        loop {
+            let tail = buffer.tail.load(Acquire);
+            if cursor == tail {
+                return if buffer.closed.load(Acquire) && buffer.tail.load(Acquire) == tail {
+                    Err(TryRecvError::Disconnected)
+                } else {
+                    Err(TryRecvError::Empty)
+                };
+            }
+            if tail - cursor > cap {
+                return Err(self.lagged(tail - cap));
+            }
+            let flag = &buffer.flags[cursor % cap];
+            let flag_val = flag.load(Acquire);
+            let stamp = flag_val >> READER_BITS;
+            if stamp > cursor + 1 {
+                // Overwritten by the event a number of laps later
+                return Err(self.lagged(tail.saturating_sub(cap).max(stamp - cap)));
+            }
+            if flag_val & READER_MASK == WRITING
+                || flag
+                    .compare_exchange(flag_val, flag_val + 1, AcqRel, Acquire)
+                    .is_err()
+            {
+                backoff.spin();
+                continue;
+            }
+            let data = unsafe { (*buffer.elements[cursor % cap].as_ptr()).clone() };
+            flag.fetch_sub(1, Release);
+            self.cursor.store(cursor + 1, Release);
+            return Ok(data);
+        }
+    }
+}
+
+impl<T> Subscriber<T> {
+    fn lagged(&self, oldest: usize) -> TryRecvError {
+        let cursor = self.cursor.load(Relaxed);
+        self.cursor.store(oldest, Release);
+        TryRecvError::Lagged(oldest - cursor)
+    }
+}
+
+// Joins at the same cursor
+impl<T> Clone for Subscriber<T> {
+    fn clone(&self) -> Self {
+        self.buffer.join(self.cursor.load(Relaxed))
+    }
+}
+
+impl<T> Drop for Publisher<T> {
+    fn drop(&mut self) {
+        self.buffer.closed.store(true, Release);
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        self.buffer
+            .cursors
+            .lock()
+            .retain(|cursor| !Arc::ptr_eq(cursor, &self.cursor));
+    }
+}
+
+impl<T> Drop for BroadcastRingBuffer<T> {
+    fn drop(&mut self) {
+        for (i, flag) in self.flags.iter().enumerate() {
+            if flag.load(Relaxed) != 0 {
+                unsafe {
+                    self.elements[i].assume_init_read();
+                }
+            }
+        }
+    }
+}
+
+unsafe impl<T: Send + Sync> Sync for BroadcastRingBuffer<T> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    pub fn gated() {
+        let (mut publisher, mut fast) = BroadcastRingBuffer::with_capacity(4).split();
+        let mut slow = fast.clone();
+        assert_eq!(fast.try_recv(), Err(TryRecvError::Empty));
+        for i in 0..4 {
+            publisher.publish(i).unwrap();
+        }
+        for i in 0..4 {
+            assert_eq!(fast.try_recv(), Ok(i));
+        }
+        // Held back by the slow one
+        assert_eq!(publisher.publish(4), Err(4));
+        assert_eq!(slow.try_recv(), Ok(0));
+        publisher.publish(4).unwrap();
+        assert_eq!(publisher.publish(5), Err(5));
+        // Leaving lets the publisher go on
+        drop(slow);
+        assert_eq!(publisher.subscribers(), 1);
+        publisher.publish(5).unwrap();
+        let mut late = publisher.subscribe();
+        publisher.publish(6).unwrap();
+        assert_eq!(late.try_recv(), Ok(6));
+        for i in 4..7 {
+            assert_eq!(fast.try_recv(), Ok(i));
+        }
+        drop(publisher);
+        assert_eq!(fast.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    pub fn lossy() {
+        let (mut publisher, mut subscriber) = BroadcastRingBuffer::lossy(4).split();
+        for i in 0..10 {
+            publisher.publish(Arc::new(i)).unwrap();
+        }
+        assert_eq!(subscriber.try_recv(), Err(TryRecvError::Lagged(6)));
+        for i in 6..10 {
+            assert_eq!(*subscriber.try_recv().unwrap(), i);
+        }
+        assert_eq!(subscriber.try_recv(), Err(TryRecvError::Empty));
+        // Overwritten events are dropped, the rest with the buffer
+        let item = Arc::new(42);
+        publisher.publish(item.clone()).unwrap();
+        drop(publisher);
+        drop(subscriber);
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+    #[test]
+    pub fn fan_out() {
+        let num = 40960;
+        for buffer in [
+            BroadcastRingBuffer::with_capacity(64),
+            BroadcastRingBuffer::lossy(64),
+        ] {
+            let lossy = buffer.lossy;
+            let (mut publisher, subscriber) = buffer.split();
+            let subscribers = (0..4)
+                .map(|_| {
+                    let mut subscriber = subscriber.clone();
+                    thread::spawn(move || {
+                        let mut received = 0;
+                        let mut expected = 0;
+                        loop {
+                            match subscriber.try_recv() {
+                                Ok(v) => {
+                                    assert_eq!(v, expected);
+                                    expected += 1;
+                                    received += 1;
+                                }
+                                Err(TryRecvError::Lagged(n)) => expected += n,
+                                Err(TryRecvError::Empty) => thread::yield_now(),
+                                Err(TryRecvError::Disconnected) => return received,
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            drop(subscriber);
+            for i in 0..num {
+                let mut v = i;
+                while let Err(back) = publisher.publish(v) {
+                    v = back;
+                    thread::yield_now();
+                }
+            }
+            drop(publisher);
+            for t in subscribers {
+                let received = t.join().unwrap();
+                assert!(received == num || lossy && received > 0);
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+// Counters for caches, kept per thread and summed up on read
+use crate::thread_local::ThreadLocal;
+use std::{
+    sync::atomic::{AtomicU64, Ordering::*},
+    time::Duration,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RemovalCause {
+    // Evicted to keep the cache within its capacity
+    Size,
+    // Lived beyond its lifetime
+    Expired,
+    // Removed by the user
+    Explicit,
+    // Overwritten by a new value for the key
+    Replaced,
+}
+
+pub type RemovalListener<K, V> = Box<dyn Fn(&K, &V, RemovalCause) + Send + Sync>;
+
+pub struct CacheMetrics {
+    counters: ThreadLocal<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    loads: AtomicU64,
+    load_failures: AtomicU64,
+    load_nanos: AtomicU64,
+    size_evictions: AtomicU64,
+    expirations: AtomicU64,
+    explicit_removals: AtomicU64,
+    replacements: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub hits: u64,
+    pub misses: u64,
+    pub loads: u64,
+    pub load_failures: u64,
+    // Summed over loads, failed ones included, as timed by the loading caller. Callers waiting on
+    // another's load are not timed, and no distribution is kept
+    pub total_load_time: Duration,
+    pub size_evictions: u64,
+    pub expirations: u64,
+    pub explicit_removals: u64,
+    pub replacements: u64,
+}
+
+impl CacheMetrics {
+    pub fn new() -> Self {
+        Self {
+            counters: ThreadLocal::new(),
+        }
+    }
+
+    pub fn record_hit(&self) {
+        self.local().hits.fetch_add(1, Relaxed);
+    }
+
+    pub fn record_miss(&self) {
+        self.local().misses.fetch_add(1, Relaxed);
+    }
+
+    pub fn record_load(&self, time: Duration, succeed: bool) {
+        let counters = self.local();
+        if succeed {
+            counters.loads.fetch_add(1, Relaxed);
+        } else {
+            counters.load_failures.fetch_add(1, Relaxed);
+        }
+        counters
+            .load_nanos
+            .fetch_add(time.as_nanos() as u64, Relaxed);
+    }
+
+    pub fn record_removal(&self, cause: RemovalCause) {
+        let counters = self.local();
+        match cause {
+            RemovalCause::Size => &counters.size_evictions,
+            RemovalCause::Expired => &counters.expirations,
+            RemovalCause::Explicit => &counters.explicit_removals,
+            RemovalCause::Replaced => &counters.replacements,
+        }
+        .fetch_add(1, Relaxed);
+    }
+
+    // Sum of all threads, not a consistent cut when other threads are recording
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mut snapshot = MetricsSnapshot::default();
+        let mut load_nanos = 0;
+        self.counters.for_each(|counters| {
+            snapshot.hits += counters.hits.load(Relaxed);
+            snapshot.misses += counters.misses.load(Relaxed);
+            snapshot.loads += counters.loads.load(Relaxed);
+            snapshot.load_failures += counters.load_failures.load(Relaxed);
+            snapshot.size_evictions += counters.size_evictions.load(Relaxed);
+            snapshot.expirations += counters.expirations.load(Relaxed);
+            snapshot.explicit_removals += counters.explicit_removals.load(Relaxed);
+            snapshot.replacements += counters.replacements.load(Relaxed);
+            load_nanos += counters.load_nanos.load(Relaxed);
+        });
+        snapshot.total_load_time = Duration::from_nanos(load_nanos);
+        snapshot
+    }
+
+    #[inline(always)]
+    fn local(&self) -> &Counters {
+        self.counters.get_or(Counters::default)
+    }
+}
+
+impl Default for CacheMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricsSnapshot {
+    pub fn requests(&self) -> u64 {
+        self.hits + self.misses
+    }
+
+    pub fn hit_ratio(&self) -> f64 {
+        let requests = self.requests();
+        if requests == 0 {
+            0.0
+        } else {
+            self.hits as f64 / requests as f64
+        }
+    }
+
+    // Removed by the cache itself
+    pub fn evictions(&self) -> u64 {
+        self.size_evictions + self.expirations
+    }
+
+    // Every removal of all causes, as reported to the removal listener
+    pub fn removals(&self) -> u64 {
+        self.evictions() + self.explicit_removals + self.replacements
+    }
+
+    pub fn average_load_time(&self) -> Duration {
+        let loads = self.loads + self.load_failures;
+        if loads == 0 {
+            Duration::ZERO
+        } else {
+            self.total_load_time / loads as u32
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn per_thread_counters() {
+        let metrics = Arc::new(CacheMetrics::new());
+        let threads = (0..8)
+            .map(|_| {
+                let metrics = metrics.clone();
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        if i % 4 == 0 {
+                            metrics.record_miss();
+                            metrics.record_load(Duration::from_micros(10), i % 8 == 0);
+                        } else {
+                            metrics.record_hit();
+                        }
+                    }
+                    metrics.record_removal(RemovalCause::Size);
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+        metrics.record_removal(RemovalCause::Explicit);
+        metrics.record_removal(RemovalCause::Replaced);
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.hits, 8 * 75);
+        assert_eq!(snapshot.misses, 8 * 25);
+        assert_eq!(snapshot.loads, 8 * 13);
+        assert_eq!(snapshot.load_failures, 8 * 12);
+        assert_eq!(snapshot.total_load_time, Duration::from_micros(10 * 8 * 25));
+        assert_eq!(snapshot.average_load_time(), Duration::from_micros(10));
+        assert_eq!(snapshot.size_evictions, 8);
+        assert_eq!(snapshot.explicit_removals, 1);
+        assert_eq!(snapshot.replacements, 1);
+        assert_eq!(snapshot.evictions(), 8);
+        assert_eq!(snapshot.removals(), 10);
+        assert_eq!(snapshot.hit_ratio(), 0.75);
+    }
+}
@@ -0,0 +1,364 @@
+// A bounded multi-producer multi-consumer channel over a ring buffer. Senders park while the
+// buffer is full and receivers while it is empty, instead of spinning on the buffer
+use crate::ring_buffer::BoxedRingBuffer;
+use parking_lot::{Condvar, Mutex};
+use std::{
+    sync::{
+        atomic::{fence, AtomicUsize, Ordering::*},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+pub fn bounded<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(cap > 0, "capacity should be positive");
+    let chan = Arc::new(Channel {
+        buffer: BoxedRingBuffer::with_capacity(cap),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        lock: Mutex::new(()),
+        not_full: Condvar::new(),
+        not_empty: Condvar::new(),
+        parked_senders: AtomicUsize::new(0),
+        parked_receivers: AtomicUsize::new(0),
+    });
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+struct Channel<T> {
+    buffer: BoxedRingBuffer<T>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    lock: Mutex<()>,
+    not_full: Condvar,
+    not_empty: Condvar,
+    // Lets the other side skip the lock when no one is parked
+    parked_senders: AtomicUsize,
+    parked_receivers: AtomicUsize,
+}
+
+pub struct Sender<T> {
+    chan: Arc<Channel<T>>,
+}
+
+pub struct Receiver<T> {
+    chan: Arc<Channel<T>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Disconnected(T),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendTimeoutError<T> {
+    Timeout(T),
+    Disconnected(T),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    Timeout,
+    Disconnected,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.chan.send_until(value, None).map_err(|e| match e {
+            SendTimeoutError::Timeout(v) | SendTimeoutError::Disconnected(v) => SendError(v),
+        })
+    }
+
+    pub fn send_timeout(&self, value: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.chan.send_until(value, Some(Instant::now() + timeout))
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.chan.receivers.load(Acquire) == 0 {
+            return Err(TrySendError::Disconnected(value));
+        }
+        self.chan.try_send(value).map_err(TrySendError::Full)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.chan.buffer.capacity()
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.chan.recv_until(None).map_err(|_| RecvError)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.chan.recv_until(Some(Instant::now() + timeout))
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.chan.try_recv() {
+            Some(value) => Ok(value),
+            None if self.chan.senders.load(Acquire) == 0 => {
+                // Sent right before the last sender left
+                self.chan.try_recv().ok_or(TryRecvError::Disconnected)
+            }
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.chan.buffer.capacity()
+    }
+}
+
+impl<T> Channel<T> {
+    fn send_until(
+        &self,
+        mut value: T,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendTimeoutError<T>> {
+        loop {
+            if self.receivers.load(Acquire) == 0 {
+                return Err(SendTimeoutError::Disconnected(value));
+            }
+            value = match self.try_send(value) {
+                Ok(()) => return Ok(()),
+                Err(value) => value,
+            };
+            let mut guard = self.lock.lock();
+            self.parked_senders.fetch_add(1, SeqCst);
+            fence(SeqCst);
+            // Retry after announcing, receivers popping from now on will come to wake us up
+            value = match self.buffer.push_back(value) {
+                Ok(_) => {
+                    self.parked_senders.fetch_sub(1, SeqCst);
+                    drop(guard);
+                    self.wake(&self.parked_receivers, &self.not_empty);
+                    return Ok(());
+                }
+                Err(value) => value,
+            };
+            let timed_out = match deadline {
+                // Disconnected before parking, reported on the next round
+                _ if self.receivers.load(Acquire) == 0 => false,
+                Some(deadline) => self.not_full.wait_until(&mut guard, deadline).timed_out(),
+                None => {
+                    self.not_full.wait(&mut guard);
+                    false
+                }
+            };
+            self.parked_senders.fetch_sub(1, SeqCst);
+            drop(guard);
+            if timed_out {
+                return self.try_send(value).map_err(SendTimeoutError::Timeout);
+            }
+        }
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            if let Some(value) = self.try_recv() {
+                return Ok(value);
+            }
+            if self.senders.load(Acquire) == 0 {
+                // Sent right before the last sender left
+                return self.try_recv().ok_or(RecvTimeoutError::Disconnected);
+            }
+            let mut guard = self.lock.lock();
+            self.parked_receivers.fetch_add(1, SeqCst);
+            fence(SeqCst);
+            // Retry after announcing, senders pushing from now on will come to wake us up
+            if let Some(value) = self.buffer.pop_front() {
+                self.parked_receivers.fetch_sub(1, SeqCst);
+                drop(guard);
+                self.wake(&self.parked_senders, &self.not_full);
+                return Ok(value);
+            }
+            let timed_out = match deadline {
+                _ if self.senders.load(Acquire) == 0 => false,
+                Some(deadline) => self.not_empty.wait_until(&mut guard, deadline).timed_out(),
+                None => {
+                    self.not_empty.wait(&mut guard);
+                    false
+                }
+            };
+            self.parked_receivers.fetch_sub(1, SeqCst);
+            drop(guard);
+            if timed_out {
+                return self.try_recv().ok_or(RecvTimeoutError::Timeout);
+            }
+        }
+    }
+
+    fn try_send(&self, value: T) -> Result<(), T> {
+        self.buffer.push_back(value)?;
+        self.wake(&self.parked_receivers, &self.not_empty);
+        Ok(())
+    }
+
+    fn try_recv(&self) -> Option<T> {
+        let value = self.buffer.pop_front()?;
+        self.wake(&self.parked_senders, &self.not_full);
+        Some(value)
+    }
+
+    // Wake one parked on the other side, if any. Taking the lock makes sure the one announced
+    // parking is already waiting on the condvar
+    fn wake(&self, parked: &AtomicUsize, cond: &Condvar) {
+        fence(SeqCst);
+        if parked.load(SeqCst) > 0 {
+            let _guard = self.lock.lock();
+            cond.notify_one();
+        }
+    }
+
+    // The other side is gone, let everyone parked find out
+    fn disconnect(&self) {
+        let _guard = self.lock.lock();
+        self.not_full.notify_all();
+        self.not_empty.notify_all();
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.chan.senders.fetch_add(1, Relaxed);
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.chan.receivers.fetch_add(1, Relaxed);
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.chan.senders.fetch_sub(1, AcqRel) == 1 {
+            self.chan.disconnect();
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.chan.receivers.fetch_sub(1, AcqRel) == 1 {
+            self.chan.disconnect();
+        }
+    }
+}
+
+unsafe impl<T: Send> Send for Sender<T> {}
+unsafe impl<T: Send> Sync for Sender<T> {}
+unsafe impl<T: Send> Send for Receiver<T> {}
+unsafe impl<T: Send> Sync for Receiver<T> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use itertools::Itertools;
+    use std::thread;
+
+    #[test]
+    pub fn serial() {
+        let (tx, rx) = bounded(2);
+        assert_eq!(tx.capacity(), 2);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        tx.send(1).unwrap();
+        assert_eq!(tx.try_send(2), Ok(()));
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+        assert_eq!(
+            tx.send_timeout(3, Duration::from_millis(10)),
+            Err(SendTimeoutError::Timeout(3))
+        );
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv_timeout(Duration::from_millis(10)), Ok(2));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+    }
+
+    #[test]
+    pub fn disconnect() {
+        let (tx, rx) = bounded(4);
+        let tx2 = tx.clone();
+        tx.send(1).unwrap();
+        drop(tx);
+        tx2.send(2).unwrap();
+        drop(tx2);
+        // Sent items are still delivered
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+        let (tx, rx) = bounded(1);
+        tx.send(1).unwrap();
+        let blocked = thread::spawn(move || tx.send(2));
+        thread::sleep(Duration::from_millis(20));
+        drop(rx);
+        assert_eq!(blocked.join().unwrap(), Err(SendError(2)));
+
+        let (tx, rx) = bounded::<usize>(1);
+        let blocked = thread::spawn(move || rx.recv());
+        thread::sleep(Duration::from_millis(20));
+        drop(tx);
+        assert_eq!(blocked.join().unwrap(), Err(RecvError));
+    }
+
+    #[test]
+    pub fn mpmc() {
+        let (tx, rx) = bounded(16);
+        let num = 4096;
+        let senders = (0..4)
+            .map(|t| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for i in 0..num {
+                        tx.send(t * num + i).unwrap();
+                    }
+                })
+            })
+            .collect_vec();
+        drop(tx);
+        let receivers = (0..4)
+            .map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || {
+                    let mut got = vec![];
+                    while let Ok(v) = rx.recv() {
+                        got.push(v);
+                    }
+                    got
+                })
+            })
+            .collect_vec();
+        drop(rx);
+        senders.into_iter().for_each(|t| t.join().unwrap());
+        let mut all = receivers
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect_vec();
+        all.sort();
+        assert_eq!(all, (0..4 * num).collect_vec());
+    }
+}
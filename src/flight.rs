@@ -0,0 +1,156 @@
+// Waiting in futures for loads in flight, without tying caches to any runtime
+use parking_lot::{Condvar, Mutex};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    thread::{self, ThreadId},
+};
+
+// Initial capacity of the maps keeping loads in flight, only so many keys are loaded at once
+pub(crate) const FLIGHTS_CAP: usize = 16;
+
+// A load in flight, misses on its key wait for it to land. It lands None when given up, then its
+// waiters go on by themselves
+pub(crate) struct Flight<T> {
+    landing: Mutex<Landing<T>>,
+    landed: Condvar,
+    // Thread polling the future leading it, blocking there would stall the future
+    led_on: Option<ThreadId>,
+}
+
+struct Landing<T> {
+    outcome: Option<Option<T>>,
+    // Futures waiting for the outcome
+    wakers: Vec<Waker>,
+}
+
+// Pending until the flight lands, woken by the landing
+pub(crate) struct Landed<'a, T>(&'a Flight<T>);
+
+impl<T: Clone> Flight<T> {
+    pub fn new(led_async: bool) -> Self {
+        Self {
+            landing: Mutex::new(Landing {
+                outcome: None,
+                wakers: vec![],
+            }),
+            landed: Condvar::new(),
+            led_on: if led_async {
+                Some(thread::current().id())
+            } else {
+                None
+            },
+        }
+    }
+
+    pub fn can_wait(&self) -> bool {
+        self.led_on != Some(thread::current().id())
+    }
+
+    // Only the first landing counts
+    pub fn land(&self, outcome: Option<T>) {
+        let mut landing = self.landing.lock();
+        if landing.outcome.is_none() {
+            landing.outcome = Some(outcome);
+        }
+        let wakers = std::mem::take(&mut landing.wakers);
+        drop(landing);
+        self.landed.notify_all();
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    pub fn wait(&self) -> Option<T> {
+        let mut landing = self.landing.lock();
+        loop {
+            if let Some(outcome) = &landing.outcome {
+                return outcome.clone();
+            }
+            self.landed.wait(&mut landing);
+        }
+    }
+
+    pub fn landed(&self) -> Landed<'_, T> {
+        Landed(self)
+    }
+}
+
+impl<'a, T: Clone> Future for Landed<'a, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut landing = self.0.landing.lock();
+        if let Some(outcome) = &landing.outcome {
+            return Poll::Ready(outcome.clone());
+        }
+        if !landing.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            landing.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+// Polls all of the futures on the current thread in turns until they are done
+#[cfg(test)]
+pub(crate) fn run_local<'a, T>(mut futures: Vec<Pin<Box<dyn Future<Output = T> + 'a>>>) -> Vec<T> {
+    use std::{
+        sync::Arc,
+        task::{Wake, Waker},
+    };
+    struct Noop;
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+    let waker = Waker::from(Arc::new(Noop));
+    let mut cx = Context::from_waker(&waker);
+    let mut outputs = (0..futures.len()).map(|_| None).collect::<Vec<_>>();
+    while outputs.iter().any(Option::is_none) {
+        for (future, output) in futures.iter_mut().zip(outputs.iter_mut()) {
+            if output.is_none() {
+                if let Poll::Ready(v) = future.as_mut().poll(&mut cx) {
+                    *output = Some(v);
+                }
+            }
+        }
+    }
+    outputs.into_iter().map(Option::unwrap).collect()
+}
+
+// A waker flagging when woken, to tell futures waiting to be woken from those polling in a loop
+#[cfg(test)]
+pub(crate) fn flag_waker() -> (std::sync::Arc<std::sync::atomic::AtomicBool>, Waker) {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc,
+    };
+    use std::task::Wake;
+    struct Flag(Arc<AtomicBool>);
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, SeqCst);
+        }
+    }
+    let woken = Arc::new(AtomicBool::new(false));
+    (woken.clone(), Waker::from(Arc::new(Flag(woken))))
+}
+
+// Pending once before getting ready, gives other futures the chance to run
+pub(crate) struct YieldNow(pub bool);
+
+pub(crate) fn yield_now() -> YieldNow {
+    YieldNow(false)
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
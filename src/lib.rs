@@ -13,6 +13,12 @@ extern crate test;
 #[macro_use]
 extern crate static_assertions;
 // pub mod deque;
+#[cfg(feature = "async")]
+pub mod async_queue;
+pub mod broadcast_ring_buffer;
+pub mod cache_metrics;
+pub mod channel;
+pub mod codec;
 pub mod linked_map;
 pub mod list;
 pub mod lru_cache;
@@ -20,7 +26,9 @@ pub mod map;
 pub mod ring_buffer;
 pub mod rw_spin;
 pub mod spin;
+pub mod spsc_ring_buffer;
 pub mod stack;
+pub mod tiny_lfu_cache;
 pub mod ttl_cache;
 
 pub mod aarc;
@@ -28,6 +36,8 @@ pub mod obj_alloc;
 pub mod rand;
 pub mod thread_local;
 
+mod flight;
+
 #[macro_use]
 mod par_list_test_macros;
 
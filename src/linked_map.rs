@@ -1,76 +1,174 @@
 // A concurrent linked hash map, fast and lock-free on iterate
-use crate::list::{LinkedRingBufferList, ListIter};
+use crate::list::{LinkedRingBufferList, ListCursor, ListItemRef, ListIter};
 use crate::map::{Map, PtrHashMap, PtrMutexGuard};
-use crate::ring_buffer::ItemPtr;
+use crate::ring_buffer::ItemHandle;
+use crossbeam_utils::Backoff;
+use std::alloc::System;
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
 use std::hash::Hash;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering::*};
 
-#[derive(Clone, Default)]
-pub struct KVPair<K: Clone + Default, V: Clone + Default>(pub K, pub V);
+#[derive(Clone)]
+pub struct KVPair<K, V>(pub K, pub V);
 
-pub struct LinkedHashMap<K: Clone + Hash + Eq + Default, V: Clone + Default, const N: usize> {
-    map: PtrHashMap<K, ItemPtr<KVPair<K, V>, N>>,
+pub type EvictFn<K, V> = Box<dyn Fn(K, V) + Send + Sync>;
+
+pub struct LinkedHashMap<K: Clone + Hash + Eq, V, const N: usize> {
+    map: PtrHashMap<K, ItemHandle<KVPair<K, V>, N>>,
     list: LinkedRingBufferList<KVPair<K, V>, N>,
+    // Keys linked in the list, a new key takes its slot before going in
+    len: AtomicUsize,
+    max_len: usize,
+    on_evict: Option<EvictFn<K, V>>,
 }
 
-impl<K: Clone + Hash + Eq + Default, V: Clone + Default, const N: usize> LinkedHashMap<K, V, N> {
+impl<K: Clone + Hash + Eq, V, const N: usize> LinkedHashMap<K, V, N> {
     pub fn with_capacity(cap: usize) -> Self {
         LinkedHashMap {
             map: PtrHashMap::with_capacity(cap),
             list: LinkedRingBufferList::new(),
+            len: AtomicUsize::new(0),
+            max_len: usize::MAX,
+            on_evict: None,
         }
     }
 
-    pub fn insert_front(&self, key: K, value: V) -> Option<V> {
-        let pair = KVPair(key.clone(), value);
-        let list_ref = self.list.push_front(pair);
-        if let Some(old) = self.map.insert(key, list_ref) {
-            unsafe { old.remove().map(|KVPair(_, v)| v) }
-        } else {
-            None
-        }
-    }
-
-    pub fn insert_back(&self, key: K, value: V) -> Option<V> {
-        let pair = KVPair(key.clone(), value);
-        let list_ref = self.list.push_back(pair);
-        if let Some(old) = self.map.insert(key, list_ref) {
-            unsafe { old.remove().map(|KVPair(_, v)| v) }
-        } else {
-            None
+    // Inserting a new key beyond the bound pops the entry at the other end, front insertions
+    // evict from the back and vice versa. The new key takes over the slot of the evicted one, so
+    // the bound holds under concurrent insertions
+    pub fn with_max_len<EF: Fn(K, V) + Send + Sync + 'static>(
+        max_len: usize,
+        on_evict: EF,
+    ) -> Self {
+        assert!(max_len > 0, "max length should be positive");
+        LinkedHashMap {
+            max_len,
+            on_evict: Some(Box::new(on_evict)),
+            ..Self::with_capacity(max_len.next_power_of_two())
         }
     }
 
-    pub fn get(&self, key: &K) -> Option<V> {
-        self.map.get(key).map(|l| unsafe { l.deref().clone().1 })
-    }
-
-    pub fn get_to_front(&self, key: &K) -> Option<V> {
-        self.get_to_general(key, true)
+    pub fn insert_front(&self, key: K, value: V) -> Option<V> {
+        self.insert_general(key, value, true)
     }
 
-    pub fn get_to_back(&self, key: &K) -> Option<V> {
-        self.get_to_general(key, false)
+    pub fn insert_back(&self, key: K, value: V) -> Option<V> {
+        self.insert_general(key, value, false)
     }
 
     #[inline(always)]
-    pub fn get_to_general(&self, key: &K, forwarding: bool) -> Option<V> {
-        self.map.lock(key).and_then(|mut l| {
-            let pair = unsafe { l.deref() }.clone();
-            let new_ref = if forwarding {
+    fn insert_general(&self, key: K, value: V, forwarding: bool) -> Option<V> {
+        let inserted = self.insert_with(KVPair(key, value), !forwarding, |pair| {
+            Ok(if forwarding {
                 self.list.push_front(pair)
             } else {
                 self.list.push_back(pair)
+            })
+        });
+        inserted.ok().flatten()
+    }
+
+    // Put the pair in the list by push and point its key to it, returns the value of the item
+    // replaced. A new key takes a slot before going in, evicting from the given end if none is left
+    #[inline(always)]
+    fn insert_with<P>(
+        &self,
+        mut pair: KVPair<K, V>,
+        evict_front: bool,
+        push: P,
+    ) -> Result<Option<V>, KVPair<K, V>>
+    where
+        P: Fn(KVPair<K, V>) -> Result<ItemHandle<KVPair<K, V>, N>, KVPair<K, V>>,
+    {
+        let key = pair.0.clone();
+        loop {
+            let new = !self.map.contains_key(&key);
+            if new {
+                self.take_slot(evict_front);
+            }
+            let list_ref = match push(pair) {
+                Ok(list_ref) => list_ref,
+                Err(pair) => {
+                    if new {
+                        self.len.fetch_sub(1, AcqRel);
+                    }
+                    return Err(pair);
+                }
             };
-            let old_ref = l.clone();
-            *l = new_ref;
-            unsafe { old_ref.remove().map(|KVPair(_, v)| v) }
+            // Respect the entry lock, other threads may be promoting or popping the same key
+            loop {
+                if let Some(mut l) = self.map.lock(&key) {
+                    let old_ref = mem::replace(&mut *l, list_ref);
+                    drop(l);
+                    if new {
+                        self.len.fetch_sub(1, AcqRel);
+                    }
+                    return Ok(self.list.remove_handle(&old_ref).map(|KVPair(_, v)| v));
+                }
+                if !new {
+                    break;
+                }
+                if self.map.insert_locked(&key, list_ref.clone()).is_some() {
+                    return Ok(None);
+                }
+            }
+            // Removed meanwhile, take the pair back to insert it as a new key
+            pair = match self.list.remove_handle(&list_ref) {
+                Some(pair) => pair,
+                None => return Ok(None),
+            };
+        }
+    }
+
+    // Count a new key in, when full the entry popped off gives its slot over
+    fn take_slot(&self, evict_front: bool) {
+        let backoff = Backoff::new();
+        loop {
+            let max_len = self.max_len;
+            let taken = self.len.fetch_update(AcqRel, Acquire, |len| {
+                if len < max_len {
+                    Some(len + 1)
+                } else {
+                    None
+                }
+            });
+            if taken.is_ok() {
+                return;
+            }
+            if let Some(KVPair(k, v)) = self.pop_entry(evict_front) {
+                if let Some(on_evict) = &self.on_evict {
+                    on_evict(k, v);
+                }
+                return;
+            }
+            // The slots are taken by keys on their way in
+            backoff.snooze();
+        }
+    }
+
+    // Hold the place of a missing key until its value is filled in. The key counts as present
+    // meanwhile, and those locking it to promote, replace or remove it wait for the value. None if
+    // the key is there or reserved already. It takes a slot of the length once filled
+    pub fn reserve(&self, key: &K) -> Option<Reservation<K, V, N>> {
+        let guard = self.map.insert_locked(key, ItemHandle::placeholder())?;
+        Some(Reservation {
+            map: self,
+            key: key.clone(),
+            guard: Some(guard),
         })
     }
 
+    pub fn is_reserved(&self, key: &K) -> bool {
+        self.map.get(key) == Some(ItemHandle::placeholder())
+    }
+
     pub fn remove(&self, key: &K) -> Option<V> {
-        self.map
-            .lock(key)
-            .and_then(|l| unsafe { PtrMutexGuard::remove(l).remove().map(|KVPair(_, v)| v) })
+        let l = self.map.lock(key)?;
+        let old_ref = PtrMutexGuard::remove(l);
+        self.len.fetch_sub(1, AcqRel);
+        self.list.remove_handle(&old_ref).map(|KVPair(_, v)| v)
     }
 
     pub fn pop_front(&self) -> Option<KVPair<K, V>> {
@@ -83,19 +181,28 @@ impl<K: Clone + Hash + Eq + Default, V: Clone + Default, const N: usize> LinkedH
 
     #[inline(always)]
     fn pop_general(&self, forwarding: bool) -> Option<KVPair<K, V>> {
+        let pair = self.pop_entry(forwarding)?;
+        self.len.fetch_sub(1, AcqRel);
+        Some(pair)
+    }
+
+    // Pop without giving up the slot of the entry
+    fn pop_entry(&self, forwarding: bool) -> Option<KVPair<K, V>> {
         loop {
             let list_item = if forwarding {
                 self.list.peek_front()
             } else {
                 self.list.peek_back()
             };
-            if let Some(pair) = list_item {
-                if let Some(KVPair(k, v)) = pair.deref() {
-                    if let Some(l) = self.map.lock(&k) {
-                        unsafe {
-                            PtrMutexGuard::remove(l).remove();
-                            return Some(KVPair(k, v));
+            if let Some(item) = list_item {
+                if let Some(key) = item.deref_with(|KVPair(k, _)| k.clone()) {
+                    if let Some(l) = self.map.lock(&key) {
+                        let old_ref = PtrMutexGuard::remove(l);
+                        if let Some(pair) = self.list.remove_handle(&old_ref) {
+                            return Some(pair);
                         }
+                        // The key is gone all the same
+                        self.len.fetch_sub(1, AcqRel);
                     }
                 }
             } else {
@@ -105,13 +212,89 @@ impl<K: Clone + Hash + Eq + Default, V: Clone + Default, const N: usize> LinkedH
     }
 
     pub fn len(&self) -> usize {
-        self.map.len()
+        self.len.load(Acquire)
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
     }
 
     pub fn contains_key(&self, key: &K) -> bool {
         self.map.contains_key(key)
     }
 
+    pub fn cursor_front(&self) -> Cursor<K, V, N> {
+        Cursor::new(self, self.list.cursor_front())
+    }
+
+    pub fn cursor_back(&self) -> Cursor<K, V, N> {
+        Cursor::new(self, self.list.cursor_back())
+    }
+
+    pub fn cursor_at(&self, key: &K) -> Option<Cursor<K, V, N>> {
+        let mut cursor = self.cursor_front();
+        if cursor.seek(key) {
+            Some(cursor)
+        } else {
+            None
+        }
+    }
+
+    fn item_of(&self, key: &K) -> Option<ListItemRef<KVPair<K, V>, N>> {
+        self.list.resolve(&self.map.get(key)?)
+    }
+
+    fn insert_at(
+        &self,
+        anchor: Option<&ListItemRef<KVPair<K, V>, N>>,
+        after: bool,
+        pair: KVPair<K, V>,
+    ) -> Result<ItemHandle<KVPair<K, V>, N>, KVPair<K, V>> {
+        self.insert_at_with(anchor, after, pair, |old, push| {
+            self.relocate(&old, push);
+        })
+    }
+
+    fn insert_at_with<R>(
+        &self,
+        anchor: Option<&ListItemRef<KVPair<K, V>, N>>,
+        after: bool,
+        pair: KVPair<K, V>,
+        relocate: R,
+    ) -> Result<ItemHandle<KVPair<K, V>, N>, KVPair<K, V>>
+    where
+        R: FnMut(
+            ListItemRef<KVPair<K, V>, N>,
+            &dyn Fn(KVPair<K, V>) -> ItemHandle<KVPair<K, V>, N>,
+        ),
+    {
+        match (anchor, after) {
+            (Some(item), true) => self.list.insert_after_with(item, pair, relocate),
+            (Some(item), false) => self.list.insert_before_with(item, pair, relocate),
+            (None, true) => Ok(self.list.push_front(pair)),
+            (None, false) => Ok(self.list.push_back(pair)),
+        }
+    }
+
+    // Move an entry split away from the anchor to its new place, keeping the key pointing to it
+    fn relocate(
+        &self,
+        old: &ListItemRef<KVPair<K, V>, N>,
+        push: &dyn Fn(KVPair<K, V>) -> ItemHandle<KVPair<K, V>, N>,
+    ) -> Option<ItemHandle<KVPair<K, V>, N>> {
+        let key = old.deref_with(|KVPair(k, _)| k.clone())?;
+        let mut l = self.map.lock(&key)?;
+        if *l != old.handle() {
+            // Promoted or replaced meanwhile, the old item is going away
+            return None;
+        }
+        // Relink before reclaiming the node, readers may still go through the old handle
+        let new_ref = push(old.item_ref().remove()?);
+        *l = new_ref.clone();
+        old.reclaim();
+        Some(new_ref)
+    }
+
     pub fn iter_front(&self) -> ListIter<KVPair<K, V>, N> {
         self.list.iter_front()
     }
@@ -145,41 +328,288 @@ impl<K: Clone + Hash + Eq + Default, V: Clone + Default, const N: usize> LinkedH
     }
 }
 
-pub struct KeyIter<'a, K: Clone + Hash + Default, V: Clone + Default, const N: usize> {
+impl<K: Clone + Hash + Eq, V: Clone, const N: usize> LinkedHashMap<K, V, N> {
+    pub fn get(&self, key: &K) -> Option<V> {
+        let backoff = Backoff::new();
+        loop {
+            let handle = self.map.get(key)?;
+            let value = self
+                .list
+                .resolve(&handle)
+                .and_then(|item| item.deref_with(|KVPair(_, v)| v.clone()));
+            if value.is_some() {
+                return value;
+            }
+            // Being moved, the key is relinked shortly, or reserved until its value is filled in
+            backoff.snooze();
+        }
+    }
+
+    pub fn get_to_front(&self, key: &K) -> Option<V> {
+        self.get_to_general(key, true)
+    }
+
+    pub fn get_to_back(&self, key: &K) -> Option<V> {
+        self.get_to_general(key, false)
+    }
+
+    #[inline(always)]
+    pub fn get_to_general(&self, key: &K, forwarding: bool) -> Option<V> {
+        self.map.lock(key).and_then(|mut l| {
+            let end = if forwarding {
+                self.list.peek_front()
+            } else {
+                self.list.peek_back()
+            };
+            let old = self.list.resolve(&l)?;
+            if end.map_or(false, |item| item.handle() == *l) {
+                // Already in place, nothing to move
+                return old.deref_with(|KVPair(_, v)| v.clone());
+            }
+            // Move the pair out of its slot instead of copying it, only the
+            // returned value is cloned
+            let pair = old.item_ref().remove()?;
+            let value = pair.1.clone();
+            *l = if forwarding {
+                self.list.push_front(pair)
+            } else {
+                self.list.push_back(pair)
+            };
+            old.reclaim();
+            Some(value)
+        })
+    }
+
+    // The entry stays readable at its old place until the key is relinked, which takes a copy
+    fn move_to(&self, anchor: Option<&ListItemRef<KVPair<K, V>, N>>, after: bool, key: &K) -> bool {
+        loop {
+            let entry = self.map.lock(key).and_then(|l| {
+                let pair = self.list.resolve(&l)?.deref()?;
+                Some((l.clone(), pair))
+            });
+            let (old_ref, pair) = match entry {
+                Some(entry) => entry,
+                None => return false,
+            };
+            if anchor.map_or(false, |item| item.handle() == old_ref) {
+                return true;
+            }
+            // The entry itself may be behind the split
+            let relocated = Cell::new(None);
+            let new_ref = match self.insert_at_with(anchor, after, pair, |old, push| {
+                let is_entry = old.handle() == old_ref;
+                if let Some(new_ref) = self.relocate(&old, push) {
+                    if is_entry {
+                        relocated.set(Some(new_ref));
+                    }
+                }
+            }) {
+                Ok(new_ref) => new_ref,
+                Err(_) => return false,
+            };
+            let expected = relocated.take().unwrap_or(old_ref);
+            match self.map.lock(key) {
+                Some(mut l) if *l == expected => {
+                    *l = new_ref;
+                    self.list.remove_handle(&expected);
+                    return true;
+                }
+                l => {
+                    // Updated or removed meanwhile, take back the copy
+                    self.list.remove_handle(&new_ref);
+                    if l.is_none() {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// A key reserved by LinkedHashMap::reserve, given up on drop unless filled
+pub struct Reservation<'a, K: Clone + Hash + Eq, V, const N: usize> {
+    map: &'a LinkedHashMap<K, V, N>,
+    key: K,
+    guard: Option<PtrMutexGuard<'a, K, ItemHandle<KVPair<K, V>, N>, System, DefaultHasher>>,
+}
+
+impl<'a, K: Clone + Hash + Eq, V, const N: usize> Reservation<'a, K, V, N> {
+    // Link the value at the front, waiters on the key go on with it
+    pub fn fill(mut self, value: V) {
+        let mut guard = self.guard.take().unwrap();
+        self.map.take_slot(false);
+        *guard = self.map.list.push_front(KVPair(self.key.clone(), value));
+    }
+}
+
+impl<'a, K: Clone + Hash + Eq, V, const N: usize> Drop for Reservation<'a, K, V, N> {
+    fn drop(&mut self) {
+        if let Some(guard) = self.guard.take() {
+            PtrMutexGuard::remove(guard);
+        }
+    }
+}
+
+// A cursor over the entries, moving past either end reaches the ghost position, from where the
+// cursor wraps around
+pub struct Cursor<'a, K: Clone + Hash + Eq, V, const N: usize> {
+    map: &'a LinkedHashMap<K, V, N>,
+    cursor: ListCursor<'a, KVPair<K, V>, N>,
+    // Splits by other cursors may move the current entry, it can be found again by the key
+    key: Option<K>,
+}
+
+impl<'a, K: Clone + Hash + Eq, V, const N: usize> Cursor<'a, K, V, N> {
+    fn new(map: &'a LinkedHashMap<K, V, N>, cursor: ListCursor<'a, KVPair<K, V>, N>) -> Self {
+        let key = Self::key_of(&cursor);
+        Self { map, cursor, key }
+    }
+
+    pub fn move_next(&mut self) -> bool {
+        self.follow();
+        self.cursor.move_next();
+        self.track()
+    }
+
+    pub fn move_prev(&mut self) -> bool {
+        self.follow();
+        self.cursor.move_prev();
+        self.track()
+    }
+
+    pub fn seek(&mut self, key: &K) -> bool {
+        match self.map.item_of(key) {
+            Some(item) => {
+                self.cursor.seek(item);
+                self.key = Some(key.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    // An existing entry of the key is moved here, returns its value. The ghost inserts to the front.
+    // Fails if the current entry has gone along with its place in the list
+    pub fn insert_after(&mut self, key: K, value: V) -> Result<Option<V>, KVPair<K, V>> {
+        self.insert_general(key, value, true)
+    }
+
+    // The ghost inserts to the back
+    pub fn insert_before(&mut self, key: K, value: V) -> Result<Option<V>, KVPair<K, V>> {
+        self.insert_general(key, value, false)
+    }
+
+    fn insert_general(&mut self, key: K, value: V, after: bool) -> Result<Option<V>, KVPair<K, V>> {
+        self.follow();
+        let anchor = self.cursor.item();
+        // Evict away from where the entry lands, from the back unless the anchor is there
+        let evict_front = match anchor {
+            Some(item) => self
+                .map
+                .list
+                .peek_back()
+                .map_or(false, |back| back.handle() == item.handle()),
+            None => !after,
+        };
+        self.map
+            .insert_with(KVPair(key, value), evict_front, |pair| {
+                self.map.insert_at(anchor, after, pair)
+            })
+    }
+
+    // Moves to the next entry
+    pub fn remove_current(&mut self) -> Option<KVPair<K, V>> {
+        self.follow();
+        let item = self.cursor.take_current();
+        self.track();
+        let item = item?;
+        let key = item.deref_with(|KVPair(k, _)| k.clone())?;
+        let l = self.map.map.lock(&key)?;
+        if *l != item.handle() {
+            return None;
+        }
+        PtrMutexGuard::remove(l);
+        self.map.len.fetch_sub(1, AcqRel);
+        item.remove()
+    }
+
+    fn follow(&mut self) {
+        if let Some(key) = &self.key {
+            let moved = self
+                .cursor
+                .item()
+                .and_then(|item| item.deref_with(|KVPair(k, _)| k == key))
+                != Some(true);
+            if moved {
+                if let Some(item) = self.map.item_of(key) {
+                    self.cursor.seek(item);
+                }
+            }
+        }
+    }
+
+    fn track(&mut self) -> bool {
+        self.key = Self::key_of(&self.cursor);
+        self.key.is_some()
+    }
+
+    fn key_of(cursor: &ListCursor<'a, KVPair<K, V>, N>) -> Option<K> {
+        cursor
+            .item()
+            .and_then(|item| item.deref_with(|KVPair(k, _)| k.clone()))
+    }
+}
+
+impl<'a, K: Clone + Hash + Eq, V: Clone, const N: usize> Cursor<'a, K, V, N> {
+    pub fn current(&self) -> Option<KVPair<K, V>> {
+        let key = self.key.as_ref()?;
+        match self.cursor.current() {
+            Some(pair) if pair.0 == *key => Some(pair),
+            _ => self.map.get(key).map(|value| KVPair(key.clone(), value)),
+        }
+    }
+
+    // Move the entry of the key right after the current one, false if the key is absent
+    pub fn move_after(&mut self, key: &K) -> bool {
+        self.follow();
+        self.map.move_to(self.cursor.item(), true, key)
+    }
+
+    pub fn move_before(&mut self, key: &K) -> bool {
+        self.follow();
+        self.map.move_to(self.cursor.item(), false, key)
+    }
+}
+
+pub struct KeyIter<'a, K, V, const N: usize> {
     iter: ListIter<'a, KVPair<K, V>, N>,
 }
 
-impl<'a, K: Clone + Hash + Default, V: Clone + Default, const N: usize> Iterator
-    for KeyIter<'a, K, V, N>
-{
+impl<'a, K: Clone, V, const N: usize> Iterator for KeyIter<'a, K, V, N> {
     type Item = K;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.iter
             .next()
-            .and_then(|i| i.deref())
-            .map(|KVPair(k, _)| k)
+            .and_then(|i| i.deref_with(|KVPair(k, _)| k.clone()))
     }
 }
 
-pub struct ValueIter<'a, K: Clone + Hash + Default, V: Clone + Default, const N: usize> {
+pub struct ValueIter<'a, K, V, const N: usize> {
     iter: ListIter<'a, KVPair<K, V>, N>,
 }
 
-impl<'a, K: Clone + Hash + Default, V: Clone + Default, const N: usize> Iterator
-    for ValueIter<'a, K, V, N>
-{
+impl<'a, K, V: Clone, const N: usize> Iterator for ValueIter<'a, K, V, N> {
     type Item = V;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.iter
             .next()
-            .and_then(|i| i.deref())
-            .map(|KVPair(_, v)| v)
+            .and_then(|i| i.deref_with(|KVPair(_, v)| v.clone()))
     }
 }
 
-impl<K: Clone + Default, V: Clone + Default> KVPair<K, V> {
+impl<K, V> KVPair<K, V> {
     pub fn key(&self) -> &K {
         &self.0
     }
@@ -193,17 +623,21 @@ impl<K: Clone + Default, V: Clone + Default> KVPair<K, V> {
     }
 }
 
-unsafe impl<K: Clone + Hash + Eq + Default, V: Clone + Default, const N: usize> Send
-    for LinkedHashMap<K, V, N>
-{
-}
+unsafe impl<K: Clone + Hash + Eq + Send, V: Send, const N: usize> Send for LinkedHashMap<K, V, N> {}
 
 #[cfg(test)]
 mod test {
     use itertools::Itertools;
 
     use super::*;
-    use std::{collections::HashSet, sync::Arc, thread};
+    use std::{
+        collections::HashSet,
+        sync::{
+            atomic::{AtomicUsize, Ordering::Relaxed},
+            Arc,
+        },
+        thread,
+    };
 
     const CAP: usize = 16;
 
@@ -218,6 +652,17 @@ mod test {
         }
     }
 
+    #[test]
+    pub fn drop_while_migrating() {
+        // Dropped right after growing, the migration thread may still hold the table chunks
+        for _ in 0..16 {
+            let map = LinkedHashMap::<usize, String, CAP>::with_capacity(4);
+            for i in 0..8 {
+                map.insert_front(i, i.to_string());
+            }
+        }
+    }
+
     #[test]
     pub fn linked_map_insertions() {
         let _ = env_logger::try_init();
@@ -277,4 +722,286 @@ mod test {
         }
         assert_eq!(num_set.len(), num_threads * num_data);
     }
+
+    #[test]
+    pub fn bounded() {
+        let evicted = Arc::new(std::sync::Mutex::new(vec![]));
+        let evicted_clone = evicted.clone();
+        let map = LinkedHashMap::<usize, usize, CAP>::with_max_len(4, move |k, _| {
+            evicted_clone.lock().unwrap().push(k)
+        });
+        for i in 0..6 {
+            map.insert_front(i, i);
+        }
+        // Updating an existing key never evicts
+        map.insert_front(3, 30);
+        assert_eq!(map.len(), 4);
+        assert_eq!(*evicted.lock().unwrap(), vec![0, 1]);
+        assert_eq!(map.iter_front_keys().collect_vec(), vec![3, 5, 4, 2]);
+        map.insert_back(6, 6);
+        assert_eq!(map.iter_front_keys().collect_vec(), vec![5, 4, 2, 6]);
+        assert_eq!(*evicted.lock().unwrap(), vec![0, 1, 3]);
+
+        let map = Arc::new(LinkedHashMap::<usize, usize, CAP>::with_max_len(
+            64,
+            |_, _| {},
+        ));
+        let threads = (0..4)
+            .map(|t| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for i in 0..512 {
+                        map.insert_front(t * 1000 + i, i);
+                        assert!(map.len() <= 64);
+                    }
+                })
+            })
+            .collect_vec();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(map.len(), 64);
+        assert_eq!(map.iter_front_keys().count(), map.len());
+    }
+
+    #[test]
+    pub fn reserve() {
+        let map = Arc::new(LinkedHashMap::<usize, usize, CAP>::with_capacity(16));
+        map.insert_front(1, 10);
+        assert!(map.reserve(&1).is_none());
+        // Given up when dropped unfilled
+        let reservation = map.reserve(&2).unwrap();
+        assert!(map.is_reserved(&2));
+        assert!(map.reserve(&2).is_none());
+        drop(reservation);
+        assert!(!map.is_reserved(&2));
+        assert_eq!(map.get(&2), None);
+        // Others wait on the key until its value is there
+        let reservation = map.reserve(&2).unwrap();
+        let waiter = {
+            let map = map.clone();
+            thread::spawn(move || map.get_to_front(&2))
+        };
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+        reservation.fill(20);
+        assert_eq!(waiter.join().unwrap(), Some(20));
+        assert_eq!(map.iter_front_keys().collect_vec(), vec![2, 1]);
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    pub fn cursor() {
+        let map = LinkedHashMap::<usize, usize, 4>::with_capacity(32);
+        for i in 0..9 {
+            map.insert_back(i, i);
+        }
+        let mut cursor = map.cursor_at(&4).unwrap();
+        assert_eq!(cursor.current().map(|p| p.0), Some(4));
+        assert_eq!(cursor.insert_after(40, 40).ok().unwrap(), None);
+        assert_eq!(cursor.insert_before(30, 30).ok().unwrap(), None);
+        assert_eq!(
+            map.iter_front_keys().collect_vec(),
+            vec![0, 1, 2, 3, 30, 4, 40, 5, 6, 7, 8]
+        );
+        // Entries moved by splits are still reachable through the map
+        for k in map.iter_front_keys().collect_vec() {
+            assert_eq!(map.get(&k), Some(k));
+        }
+        assert_eq!(map.get_to_front(&5), Some(5));
+        assert!(cursor.move_after(&0));
+        assert!(cursor.move_before(&8));
+        assert!(!cursor.move_after(&100));
+        // Inserting an existing key moves it
+        assert_eq!(cursor.insert_after(1, 10).ok().unwrap(), Some(1));
+        assert_eq!(
+            map.iter_front_keys().collect_vec(),
+            vec![5, 2, 3, 30, 8, 4, 1, 0, 40, 6, 7]
+        );
+        assert!(cursor.move_next());
+        assert_eq!(cursor.remove_current().map(|p| p.0), Some(1));
+        assert_eq!(cursor.current().map(|p| p.0), Some(0));
+        assert!(cursor.move_prev());
+        assert!(cursor.move_prev());
+        assert_eq!(cursor.current().map(|p| p.0), Some(8));
+        assert_eq!(map.len(), 10);
+        assert!(map.get(&1).is_none());
+        for k in map.iter_front_keys().collect_vec() {
+            assert_eq!(map.get(&k), Some(k));
+        }
+        let mut ghost = map.cursor_back();
+        assert!(!ghost.move_next());
+        ghost.insert_before(99, 99).ok().unwrap();
+        ghost.insert_after(98, 98).ok().unwrap();
+        assert_eq!(map.iter_front_keys().next(), Some(98));
+        assert_eq!(map.iter_back_keys().next(), Some(99));
+    }
+
+    #[test]
+    pub fn bounded_cursor() {
+        let evicted = Arc::new(std::sync::Mutex::new(vec![]));
+        let map = {
+            let evicted = evicted.clone();
+            LinkedHashMap::<usize, usize, 4>::with_max_len(4, move |k, _| {
+                evicted.lock().unwrap().push(k)
+            })
+        };
+        for i in 0..4 {
+            map.insert_back(i, i);
+        }
+        // Landing by the back evicts the front, the anchor stays
+        let mut cursor = map.cursor_at(&3).unwrap();
+        assert_eq!(cursor.insert_before(30, 30).ok().unwrap(), None);
+        assert_eq!(cursor.insert_after(40, 40).ok().unwrap(), None);
+        assert_eq!(map.iter_front_keys().collect_vec(), vec![2, 30, 3, 40]);
+        // Landing elsewhere evicts the back
+        let mut cursor = map.cursor_at(&2).unwrap();
+        assert_eq!(cursor.insert_after(20, 20).ok().unwrap(), None);
+        assert_eq!(map.iter_front_keys().collect_vec(), vec![2, 20, 30, 3]);
+        let mut ghost = map.cursor_back();
+        assert!(!ghost.move_next());
+        ghost.insert_before(99, 99).ok().unwrap();
+        assert_eq!(map.iter_front_keys().collect_vec(), vec![20, 30, 3, 99]);
+        assert_eq!(*evicted.lock().unwrap(), vec![0, 1, 40, 2]);
+        assert_eq!(map.len(), 4);
+    }
+
+    #[test]
+    pub fn concurrent_cursor() {
+        let map = Arc::new(LinkedHashMap::<usize, usize, 8>::with_capacity(64));
+        for i in 0..64 {
+            map.insert_back(i, i);
+        }
+        let threads = (0..4)
+            .map(|t| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for i in 0..256 {
+                        let key = (t * 7 + i * 13) % 64;
+                        match i % 4 {
+                            0 => {
+                                if let Some(mut cursor) = map.cursor_at(&key) {
+                                    cursor.move_after(&((key + 1) % 64));
+                                }
+                            }
+                            1 => {
+                                if let Some(mut cursor) = map.cursor_at(&key) {
+                                    let _ = cursor.insert_before(key, key);
+                                }
+                            }
+                            2 => {
+                                map.get_to_front(&key);
+                            }
+                            _ => {
+                                map.insert_back(key, key);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect_vec();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let keys = map.iter_front_keys().collect_vec();
+        assert_eq!(keys.len(), 64);
+        assert_eq!(keys.iter().collect::<HashSet<_>>().len(), 64);
+        for k in keys {
+            assert_eq!(map.get(&k), Some(k));
+        }
+    }
+
+    #[derive(Default)]
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Clone for Counted {
+        fn clone(&self) -> Self {
+            self.0.fetch_add(1, Relaxed);
+            Counted(self.0.clone())
+        }
+    }
+
+    #[test]
+    pub fn promotion_moves_pairs() {
+        let clones = Arc::new(AtomicUsize::new(0));
+        let map = LinkedHashMap::<usize, Counted, 4>::with_capacity(16);
+        for i in 0..8 {
+            map.insert_back(i, Counted(clones.clone()));
+        }
+        clones.store(0, Relaxed);
+        for i in (0..8).rev() {
+            assert!(map.get_to_front(&i).is_some());
+        }
+        // Only the returned values are cloned
+        assert_eq!(clones.load(Relaxed), 8);
+        clones.store(0, Relaxed);
+        assert!(map.get_to_front(&0).is_some());
+        assert_eq!(clones.load(Relaxed), 1);
+        assert_eq!(map.iter_front_keys().collect_vec(), (0..8).collect_vec());
+    }
+
+    // Neither clone nor default
+    struct Handle(Box<dyn Fn() -> usize + Send>);
+
+    #[test]
+    pub fn non_clone_values() {
+        let map = LinkedHashMap::<usize, Handle, 4>::with_capacity(16);
+        for i in 0..8 {
+            assert!(map.insert_back(i, Handle(Box::new(move || i))).is_none());
+        }
+        let old = map.insert_back(3, Handle(Box::new(|| 42))).unwrap();
+        assert_eq!((old.0)(), 3);
+        assert_eq!(map.remove(&5).map(|h| (h.0)()), Some(5));
+        let KVPair(k, v) = map.pop_front().unwrap();
+        assert_eq!((k, (v.0)()), (0, 0));
+        let mut cursor = map.cursor_at(&2).unwrap();
+        assert!(matches!(
+            cursor.insert_after(9, Handle(Box::new(|| 9))),
+            Ok(None)
+        ));
+        let KVPair(k, _) = cursor.remove_current().unwrap();
+        assert_eq!(k, 2);
+        assert_eq!(map.iter_front_keys().collect_vec(), vec![1, 9, 4, 6, 7, 3]);
+        let values = map
+            .iter_front()
+            .filter_map(|item| item.deref_with(|KVPair(_, v)| (v.0)()))
+            .collect_vec();
+        assert_eq!(values, vec![1, 9, 4, 6, 7, 42]);
+    }
+
+    #[test]
+    pub fn reclaim_promoted() {
+        let map = Arc::new(LinkedHashMap::<usize, usize, 8>::with_capacity(512));
+        for i in 0..256 {
+            map.insert_back(i, i);
+        }
+        for _ in 0..16 {
+            for i in 0..256 {
+                assert_eq!(map.get_to_back(&i), Some(i));
+            }
+        }
+        // Drained nodes are unlinked, only the ends may be left partially filled
+        assert!(map.list.node_count() <= 256 / 7 + 2);
+        let threads = (0..4)
+            .map(|t| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for r in 0..64 {
+                        for i in (0..256).filter(|i| i % 4 == t) {
+                            if r % 2 == 0 {
+                                assert_eq!(map.get_to_front(&i), Some(i));
+                            } else {
+                                assert_eq!(map.get(&i), Some(i));
+                            }
+                        }
+                    }
+                })
+            })
+            .collect_vec();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+        assert_eq!(map.len(), 256);
+        let mut keys = map.iter_front_keys().collect_vec();
+        keys.sort();
+        assert_eq!(keys, (0..256).collect_vec());
+    }
 }
@@ -1,25 +1,41 @@
 use crossbeam_epoch::*;
 use crossbeam_utils::Backoff;
 
-use crate::ring_buffer::{ItemIter, ItemPtr, ItemRef, RingBuffer};
+use crate::ring_buffer::{ItemHandle, ItemIter, ItemPtr, ItemRef, RingBuffer};
 use parking_lot::Mutex;
-use std::sync::atomic::Ordering::*;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::*};
+use std::sync::Arc;
 
 // A mostly lock-free list with linked ring buffers
 
+// Ids tell handles of different lists apart, never reused
+static LIST_ID: AtomicUsize = AtomicUsize::new(1);
+
 pub struct LinkedRingBufferList<T, const B: usize> {
     head: Atomic<RingBufferNode<T, B>>,
     tail: Atomic<RingBufferNode<T, B>>,
+    id: usize,
+    // Unlinked nodes are reused instead of freed until the list drops, handles may point into them
+    spare: Arc<NodePool<T, B>>,
+}
+
+struct NodePool<T, const N: usize> {
+    nodes: Mutex<Vec<*mut RingBufferNode<T, N>>>,
 }
 
+// Buffer goes first so item pointers can find their node
+#[repr(C)]
 pub struct RingBufferNode<T, const N: usize> {
     pub buffer: RingBuffer<T, N>,
     prev: Atomic<Self>,
     next: Atomic<Self>,
     lock: Mutex<()>,
+    // Nodes being filled by a split or pushed to shall not be unlinked while empty
+    pins: AtomicUsize,
+    removed: AtomicBool,
 }
 
-impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
+impl<T, const N: usize> LinkedRingBufferList<T, N> {
     pub fn new() -> Self {
         let guard = crossbeam_epoch::pin();
         let head_ptr = Owned::new(RingBufferNode::new()).into_shared(&guard);
@@ -31,24 +47,29 @@ impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
         Self {
             head: Atomic::from(head_ptr),
             tail: Atomic::from(tail_ptr),
+            id: LIST_ID.fetch_add(1, Relaxed),
+            spare: Arc::new(NodePool {
+                nodes: Mutex::new(vec![]),
+            }),
         }
     }
-    pub fn push_front(&self, mut val: T) -> ItemPtr<T, N> {
+
+    pub fn push_front(&self, mut val: T) -> ItemHandle<T, N> {
         let guard = crossbeam_epoch::pin();
         let backoff = Backoff::new();
         loop {
             let head_ptr = self.head.load(Acquire, &guard);
             let head_node = unsafe { head_ptr.deref() };
-            match head_node.buffer.push_front(val) {
+            match head_node.push_pinned(val, RingBuffer::push_front) {
                 Ok(r) => {
-                    return r.to_ptr();
+                    return self.handle_of(&r);
                 }
                 Err(v) => {
                     let head_lock = head_node.lock.try_lock();
                     if head_lock.is_some() && head_node.prev.load(Acquire, &guard).is_null() {
-                        let new_node = RingBufferNode::new();
+                        let new_node = self.new_node();
                         new_node.next.store(head_ptr, Relaxed);
-                        let new_node_ptr = Owned::new(new_node).into_shared(&guard);
+                        let new_node_ptr = new_node.into_shared(&guard);
                         let _new_node_lock = unsafe { new_node_ptr.deref().lock.lock() };
                         if self
                             .head
@@ -57,9 +78,7 @@ impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
                         {
                             head_node.prev.store(new_node_ptr, Release);
                         } else {
-                            unsafe {
-                                new_node_ptr.into_owned();
-                            }
+                            self.spare.put(new_node_ptr.as_raw());
                         }
                     }
                     val = v;
@@ -69,22 +88,22 @@ impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
         }
     }
 
-    pub fn push_back(&self, mut val: T) -> ItemPtr<T, N> {
+    pub fn push_back(&self, mut val: T) -> ItemHandle<T, N> {
         let guard = crossbeam_epoch::pin();
         let backoff = Backoff::new();
         loop {
             let tail_ptr = self.tail.load(Acquire, &guard);
             let tail_node = unsafe { tail_ptr.deref() };
-            match tail_node.buffer.push_back(val) {
+            match tail_node.push_pinned(val, RingBuffer::push_back) {
                 Ok(r) => {
-                    return r.to_ptr();
+                    return self.handle_of(&r);
                 }
                 Err(v) => {
                     let tail_lock = tail_node.lock.try_lock();
                     if tail_lock.is_some() && tail_node.next.load(Acquire, &guard).is_null() {
-                        let new_node = RingBufferNode::new();
+                        let new_node = self.new_node();
                         new_node.prev.store(tail_ptr, Relaxed);
-                        let new_node_ptr = Owned::new(new_node).into_shared(&guard);
+                        let new_node_ptr = new_node.into_shared(&guard);
                         let _new_node_lock = unsafe { new_node_ptr.deref().lock.lock() };
                         if self
                             .tail
@@ -93,9 +112,7 @@ impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
                         {
                             tail_node.next.store(new_node_ptr, Release);
                         } else {
-                            unsafe {
-                                new_node_ptr.into_owned();
-                            }
+                            self.spare.put(new_node_ptr.as_raw());
                         }
                     }
                     val = v;
@@ -115,7 +132,6 @@ impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
                 return Some(obj);
             }
             // Prev node is empty, shall move to next node
-            let mut remains = vec![];
             {
                 let head_next = head_node.next.load(Acquire, &guard);
                 let head_next_node = unsafe { head_next.deref() };
@@ -130,22 +146,24 @@ impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
                     && head_node.prev.load(Acquire, &guard).is_null()
                     && head_node.next.load(Acquire, &guard) == head_next
                     && head_next_node.prev.load(Acquire, &guard) == head_ptr
-                    && self
-                        .head
-                        .compare_exchange(head_ptr, head_next, AcqRel, Acquire, &guard)
-                        .is_ok()
+                    && head_node.retire()
                 {
-                    head_next_node.prev.store(Shared::null(), Release);
-                    // Need to keep prev and next reference for iterator
-                    remains = head_node.buffer.pop_all();
-                    unsafe {
-                        guard.defer_destroy(head_ptr);
+                    // Pushed to before it was marked, pop again. Items never leave their node
+                    // but by removal, handles to them would be stale otherwise
+                    if head_node.buffer.peek_back().is_none()
+                        && self
+                            .head
+                            .compare_exchange(head_ptr, head_next, AcqRel, Acquire, &guard)
+                            .is_ok()
+                    {
+                        head_next_node.prev.store(Shared::null(), Release);
+                        // Need to keep prev and next reference for iterator
+                        self.recycle(head_ptr, &guard);
+                    } else {
+                        head_node.removed.store(false, Release);
                     }
                 }
             }
-            for r in remains {
-                self.push_front(r);
-            }
             backoff.spin();
         }
     }
@@ -160,7 +178,6 @@ impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
                 return Some(obj);
             }
             // Prev node is empty, shall move to next node
-            let mut remains = vec![];
             {
                 let tail_prev = tail_node.prev.load(Acquire, &guard);
                 let tail_prev_node = unsafe { tail_prev.deref() };
@@ -175,35 +192,89 @@ impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
                     && tail_node.next.load(Acquire, &guard).is_null()
                     && tail_node.prev.load(Acquire, &guard) == tail_prev
                     && tail_prev_node.next.load(Acquire, &guard) == tail_ptr
-                    && self
-                        .tail
-                        .compare_exchange(tail_ptr, tail_prev, AcqRel, Acquire, &guard)
-                        .is_ok()
+                    && tail_node.retire()
                 {
-                    tail_prev_node.next.store(Shared::null(), Release);
-                    // Need to keep prev and next reference for iterator
-                    remains = tail_node.buffer.pop_all();
-                    unsafe {
-                        guard.defer_destroy(tail_ptr);
+                    // Pushed to before it was marked, pop again
+                    if tail_node.buffer.peek_back().is_none()
+                        && self
+                            .tail
+                            .compare_exchange(tail_ptr, tail_prev, AcqRel, Acquire, &guard)
+                            .is_ok()
+                    {
+                        tail_prev_node.next.store(Shared::null(), Release);
+                        // Need to keep prev and next reference for iterator
+                        self.recycle(tail_ptr, &guard);
+                    } else {
+                        tail_node.removed.store(false, Release);
                     }
                 }
             }
-            for r in remains {
-                self.push_back(r);
-            }
             backoff.spin();
         }
     }
 
+    // Push all the items to the back in runs, returns the number pushed
+    pub fn push_many<I: IntoIterator<Item = T>>(&self, items: I) -> usize {
+        let guard = crossbeam_epoch::pin();
+        let mut items = items.into_iter().peekable();
+        let mut pushed = 0;
+        while items.peek().is_some() {
+            let tail_ptr = self.tail.load(Acquire, &guard);
+            let tail_node = unsafe { tail_ptr.deref() };
+            let num = tail_node.push_many_pinned(&mut items);
+            if num == 0 {
+                // Tail node is full, let push_back grow the list
+                if let Some(val) = items.next() {
+                    self.push_back(val);
+                    pushed += 1;
+                }
+            }
+            pushed += num;
+        }
+        pushed
+    }
+
+    // Pop from the front into the vector until the list is empty or the max is reached, returns
+    // the number popped
+    pub fn pop_many(&self, out: &mut Vec<T>, max: usize) -> usize {
+        let guard = crossbeam_epoch::pin();
+        let mut popped = 0;
+        while popped < max {
+            let head_ptr = self.head.load(Acquire, &guard);
+            let head_node = unsafe { head_ptr.deref() };
+            popped += head_node.buffer.pop_many(out, max - popped);
+            if popped == max {
+                break;
+            }
+            let head_next = head_node.next.load(Acquire, &guard);
+            let head_next_node = unsafe { head_next.deref() };
+            if head_next_node.next.load(Acquire, &guard).is_null() {
+                // Approching back most node, the head stays
+                popped += head_next_node.buffer.pop_many(out, max - popped);
+                break;
+            }
+            // Let pop_front move the head to the next node
+            match self.pop_front() {
+                Some(val) => {
+                    out.push(val);
+                    popped += 1;
+                }
+                None => break,
+            }
+        }
+        popped
+    }
+
     pub fn peek_front(&self) -> Option<ListItemRef<T, N>> {
         let guard = crossbeam_epoch::pin();
         let backoff = Backoff::new();
         let mut node_ptr = self.head.load(Acquire, &guard);
-        let obj_idx;
+        let (obj_idx, gen);
         loop {
             let node = unsafe { node_ptr.deref() };
             if let Some(o) = node.buffer.peek_front() {
                 obj_idx = o.idx;
+                gen = o.gen;
                 break;
             } else {
                 node_ptr = node.next.load(Acquire, &guard);
@@ -217,6 +288,7 @@ impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
         Some(ListItemRef {
             guard,
             obj_idx,
+            gen,
             node_ptr,
             list: self,
         })
@@ -226,11 +298,12 @@ impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
         let guard = crossbeam_epoch::pin();
         let backoff = Backoff::new();
         let mut node_ptr = self.tail.load(Acquire, &guard);
-        let obj_idx;
+        let (obj_idx, gen);
         loop {
             let node = unsafe { node_ptr.deref() };
             if let Some(o) = node.buffer.peek_back() {
                 obj_idx = o.idx;
+                gen = o.gen;
                 break;
             } else {
                 node_ptr = node.prev.load(Acquire, &guard);
@@ -244,11 +317,345 @@ impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
         Some(ListItemRef {
             guard,
             obj_idx,
+            gen,
             node_ptr,
             list: self,
         })
     }
 
+    pub fn cursor_front(&self) -> ListCursor<T, N> {
+        ListCursor {
+            list: self,
+            current: self.peek_front(),
+        }
+    }
+
+    pub fn cursor_back(&self) -> ListCursor<T, N> {
+        ListCursor {
+            list: self,
+            current: self.peek_back(),
+        }
+    }
+
+    // Insert the value right after the item. Items behind the item in the same buffer are moved to
+    // a new node, each of them handed to `relocate` with the function pushing to the new node so
+    // owners of item pointers can update theirs. Fails if the node of the item has gone
+    pub(crate) fn insert_after_with<R>(
+        &self,
+        item: &ListItemRef<T, N>,
+        val: T,
+        relocate: R,
+    ) -> Result<ItemHandle<T, N>, T>
+    where
+        R: FnMut(ListItemRef<T, N>, &dyn Fn(T) -> ItemHandle<T, N>),
+    {
+        self.insert_general(item, val, true, relocate)
+    }
+
+    // Same as insert_after_with, moving items ahead of the item instead
+    pub(crate) fn insert_before_with<R>(
+        &self,
+        item: &ListItemRef<T, N>,
+        val: T,
+        relocate: R,
+    ) -> Result<ItemHandle<T, N>, T>
+    where
+        R: FnMut(ListItemRef<T, N>, &dyn Fn(T) -> ItemHandle<T, N>),
+    {
+        self.insert_general(item, val, false, relocate)
+    }
+
+    fn insert_general<R>(
+        &self,
+        item: &ListItemRef<T, N>,
+        val: T,
+        after: bool,
+        mut relocate: R,
+    ) -> Result<ItemHandle<T, N>, T>
+    where
+        R: FnMut(ListItemRef<T, N>, &dyn Fn(T) -> ItemHandle<T, N>),
+    {
+        let guard = crossbeam_epoch::pin();
+        let node_ref = Shared::from(item.node_ptr);
+        let node = unsafe { node_ref.deref() };
+        let beside = |idx| {
+            if after {
+                node.buffer.next_of(idx)
+            } else {
+                node.buffer.prev_of(idx)
+            }
+        };
+        let val = {
+            // Hold the lock so the node won't be unlinked while pushing
+            let _node_lock = node.lock.lock();
+            if node.removed.load(Acquire) {
+                return Err(val);
+            }
+            if beside(item.obj_idx).is_some() {
+                val
+            } else {
+                let pushed = if after {
+                    node.buffer.push_back(val)
+                } else {
+                    node.buffer.push_front(val)
+                };
+                match pushed {
+                    Ok(r) => return Ok(self.handle_of(&r)),
+                    Err(val) => val,
+                }
+            }
+        };
+        let split_ref = match self.split(node_ref, after, &guard) {
+            Some(split_ref) => split_ref,
+            None => return Err(val),
+        };
+        let split = unsafe { split_ref.deref() };
+        let push = |v: T| {
+            let pushed = if after {
+                split.buffer.push_back(v)
+            } else {
+                split.buffer.push_front(v)
+            };
+            match pushed {
+                Ok(r) => self.handle_of(&r),
+                // Only when the split node became the end and took pushes to the end
+                Err(v) if after => self.push_back(v),
+                Err(v) => self.push_front(v),
+            }
+        };
+        let res = push(val);
+        let mut moving = vec![];
+        let mut next = beside(item.obj_idx);
+        while let Some(r) = next {
+            moving.push((r.idx, r.gen));
+            next = beside(r.idx);
+        }
+        for (obj_idx, gen) in moving {
+            let old = ListItemRef {
+                guard: crossbeam_epoch::pin(),
+                obj_idx,
+                gen,
+                list: self,
+                node_ptr: item.node_ptr,
+            };
+            relocate(old, &push);
+        }
+        self.unpin(split_ref, &guard);
+        Ok(res)
+    }
+
+    // Link a new pinned node beside the node, None if the node is no longer in the list
+    fn split<'g>(
+        &self,
+        node_ref: Shared<'g, RingBufferNode<T, N>>,
+        after: bool,
+        guard: &'g Guard,
+    ) -> Option<Shared<'g, RingBufferNode<T, N>>> {
+        let node = unsafe { node_ref.deref() };
+        loop {
+            // Lock from front to back, same as unlinking
+            let prev_ptr = node.prev.load(Acquire, guard);
+            let _prev_lock = unsafe { prev_ptr.as_ref() }
+                .filter(|_| !after)
+                .map(|prev| prev.lock.lock());
+            let _node_lock = node.lock.lock();
+            if node.removed.load(Acquire) {
+                return None;
+            }
+            let (front_ptr, back_ptr) = if after {
+                let mut next_ptr = node.next.load(Acquire, guard);
+                if next_ptr.is_null() {
+                    // Splitting the back node, a new back node takes pushes to the back meanwhile
+                    next_ptr = match self.extend(&self.tail, node_ref, after, guard) {
+                        Some(next_ptr) => next_ptr,
+                        // A push extended it first, link to its new node instead
+                        None => continue,
+                    };
+                }
+                (node_ref, next_ptr)
+            } else {
+                if node.prev.load(Acquire, guard) != prev_ptr {
+                    continue;
+                }
+                if prev_ptr.is_null() {
+                    match self.extend(&self.head, node_ref, after, guard) {
+                        Some(prev_ptr) => (prev_ptr, node_ref),
+                        None => continue,
+                    }
+                } else {
+                    (prev_ptr, node_ref)
+                }
+            };
+            let front = unsafe { front_ptr.deref() };
+            let back = unsafe { back_ptr.deref() };
+            let _new_end_lock = if after { Some(back.lock.lock()) } else { None };
+            let split = self.new_node();
+            split.pins.store(1, Relaxed);
+            split.prev.store(front_ptr, Relaxed);
+            split.next.store(back_ptr, Relaxed);
+            let split_ptr = split.into_shared(guard);
+            front.next.store(split_ptr, Release);
+            back.prev.store(split_ptr, Release);
+            return Some(split_ptr);
+        }
+    }
+
+    // Add a new node at the end the node is at, returns the new node. None if another one was added
+    // there first, it is linked to the node shortly
+    fn extend<'g>(
+        &self,
+        end: &Atomic<RingBufferNode<T, N>>,
+        node_ref: Shared<'g, RingBufferNode<T, N>>,
+        after: bool,
+        guard: &'g Guard,
+    ) -> Option<Shared<'g, RingBufferNode<T, N>>> {
+        let node = unsafe { node_ref.deref() };
+        let new_node = self.new_node();
+        if after {
+            new_node.prev.store(node_ref, Relaxed);
+        } else {
+            new_node.next.store(node_ref, Relaxed);
+        }
+        let new_ptr = new_node.into_shared(guard);
+        if end
+            .compare_exchange(node_ref, new_ptr, AcqRel, Acquire, guard)
+            .is_err()
+        {
+            self.spare.put(new_ptr.as_raw());
+            return None;
+        }
+        if after {
+            node.next.store(new_ptr, Release);
+        } else {
+            node.prev.store(new_ptr, Release);
+        }
+        Some(new_ptr)
+    }
+
+    fn unpin<'g>(&self, node_ref: Shared<'g, RingBufferNode<T, N>>, guard: &'g Guard) {
+        let node = unsafe { node_ref.deref() };
+        {
+            let _node_lock = node.lock.lock();
+            node.pins.fetch_sub(1, AcqRel);
+        }
+        if node.buffer.peek_back().is_none() {
+            self.unlink_empty(node_ref, guard);
+        }
+    }
+
+    // Unlink the internal node if it is empty, end nodes are left to pop_front and pop_back
+    fn unlink_empty<'g>(&self, node_ref: Shared<'g, RingBufferNode<T, N>>, guard: &'g Guard) {
+        let node = unsafe { node_ref.deref() };
+        loop {
+            let prev_ptr = node.prev.load(Acquire, guard);
+            let next_ptr = node.next.load(Acquire, guard);
+            let (prev, next) = match unsafe { (prev_ptr.as_ref(), next_ptr.as_ref()) } {
+                (Some(prev), Some(next)) => (prev, next),
+                _ => return,
+            };
+            let _prev_lock = prev.lock.lock();
+            let _node_lock = node.lock.lock();
+            let _next_lock = next.lock.lock();
+            if node.removed.load(Acquire) || node.buffer.peek_back().is_some() {
+                return;
+            }
+            // Neighbours may have been unlinked before locking, which updates the links of this node
+            if node.prev.load(Acquire, guard) == prev_ptr
+                && node.next.load(Acquire, guard) == next_ptr
+                && prev.next.load(Acquire, guard) == node_ref
+                && next.prev.load(Acquire, guard) == node_ref
+            {
+                if !node.retire() {
+                    return;
+                }
+                if node.buffer.peek_back().is_some() {
+                    // Pushed to before it was marked
+                    node.removed.store(false, Release);
+                    return;
+                }
+                // Nothing can be pushed to it from now on, it is left with no items to move out
+                prev.next.store(next_ptr, Release);
+                next.prev.store(prev_ptr, Release);
+                self.recycle(node_ref, guard);
+                return;
+            }
+        }
+    }
+
+    // A pooled node if there is one. Pooled nodes are drained and no longer reachable from the list
+    fn new_node(&self) -> Owned<RingBufferNode<T, N>> {
+        match self.spare.take() {
+            Some(node_ptr) => {
+                let node = unsafe { Owned::from_raw(node_ptr) };
+                node.prev.store(Shared::null(), Relaxed);
+                node.next.store(Shared::null(), Relaxed);
+                node.pins.store(0, Relaxed);
+                node.removed.store(false, Relaxed);
+                node.buffer.reset();
+                node
+            }
+            None => Owned::new(RingBufferNode::new()),
+        }
+    }
+
+    // Pool the unlinked node once no one can be walking through it
+    fn recycle<'g>(&self, node_ref: Shared<'g, RingBufferNode<T, N>>, guard: &'g Guard) {
+        let spare = self.spare.clone();
+        let node_ptr = node_ref.as_raw() as usize;
+        unsafe {
+            guard.defer_unchecked(move || spare.put(node_ptr as *const RingBufferNode<T, N>));
+        }
+    }
+
+    fn handle_of(&self, item: &ItemRef<T, N>) -> ItemHandle<T, N> {
+        ItemHandle {
+            owner: self.id,
+            ..item.handle()
+        }
+    }
+
+    // The item the handle refers to, None if the handle is from another list or the item has gone
+    pub fn resolve(&self, handle: &ItemHandle<T, N>) -> Option<ListItemRef<T, N>> {
+        if handle.owner != self.id {
+            return None;
+        }
+        // Nodes of the list are never freed before the list, buffers go first in them
+        let item = ListItemRef {
+            guard: crossbeam_epoch::pin(),
+            obj_idx: handle.idx,
+            gen: handle.gen,
+            list: self,
+            node_ptr: handle.buffer as *const RingBufferNode<T, N>,
+        };
+        if item.item_ref().is_live() {
+            Some(item)
+        } else {
+            None
+        }
+    }
+
+    pub fn remove_handle(&self, handle: &ItemHandle<T, N>) -> Option<T> {
+        self.resolve(handle)?.remove()
+    }
+
+    // Unchecked fast path of remove_handle. Unlike ItemPtr::remove, the node is reclaimed once
+    // emptied. The item shall still be in the list, or its node be protected by a guard
+    pub unsafe fn remove_ptr(&self, ptr: &ItemPtr<T, N>) -> Option<T> {
+        ListItemRef::from_ptr(self, ptr, crossbeam_epoch::pin()).remove()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn node_count(&self) -> usize {
+        let guard = crossbeam_epoch::pin();
+        let mut node_ptr = self.head.load(Acquire, &guard);
+        let mut count = 0;
+        while let Some(node) = unsafe { node_ptr.as_ref() } {
+            count += 1;
+            node_ptr = node.next.load(Acquire, &guard);
+        }
+        count
+    }
+
     pub fn iter_front(&self) -> ListIter<T, N> {
         self.iter_general(true)
     }
@@ -282,74 +689,155 @@ impl<T: Clone + Default, const N: usize> LinkedRingBufferList<T, N> {
     }
 }
 
-impl<T: Clone + Default, const N: usize> RingBufferNode<T, N> {
+impl<T, const N: usize> RingBufferNode<T, N> {
+    // The node may have turned internal since it was loaded as an end, keep it from being
+    // unlinked in the middle of the push
+    fn push_pinned<'a>(
+        &'a self,
+        val: T,
+        push: fn(&'a RingBuffer<T, N>, T) -> Result<ItemRef<'a, T, N>, T>,
+    ) -> Result<ItemRef<'a, T, N>, T> {
+        self.pins.fetch_add(1, SeqCst);
+        let res = if self.removed.load(SeqCst) {
+            Err(val)
+        } else {
+            push(&self.buffer, val)
+        };
+        self.pins.fetch_sub(1, Release);
+        res
+    }
+
+    fn push_many_pinned<I: Iterator<Item = T>>(&self, items: I) -> usize {
+        self.pins.fetch_add(1, SeqCst);
+        let num = if self.removed.load(SeqCst) {
+            0
+        } else {
+            self.buffer.push_many(items)
+        };
+        self.pins.fetch_sub(1, Release);
+        num
+    }
+
+    // Mark the node removed unless pinned. Marking goes before checking the pins, the other way
+    // around for pushers, so either side sees the other. Called with the node locked
+    fn retire(&self) -> bool {
+        self.removed.store(true, SeqCst);
+        if self.pins.load(SeqCst) > 0 {
+            self.removed.store(false, Release);
+            false
+        } else {
+            true
+        }
+    }
+
     pub fn new() -> Self {
         Self {
             prev: Atomic::null(),
             next: Atomic::null(),
             buffer: RingBuffer::<T, N>::new(),
             lock: Mutex::new(()),
+            pins: AtomicUsize::new(0),
+            removed: AtomicBool::new(false),
         }
     }
 }
 
-pub struct ListItemRef<'a, T: Clone, const N: usize> {
+pub struct ListItemRef<'a, T, const N: usize> {
     guard: Guard,
     obj_idx: usize,
+    gen: usize,
     list: &'a LinkedRingBufferList<T, N>,
     node_ptr: *const RingBufferNode<T, N>,
 }
 
-impl<'a, T: Clone + Default, const N: usize> ListItemRef<'a, T, N> {
+impl<'a, T: Clone, const N: usize> ListItemRef<'a, T, N> {
     pub fn deref(&self) -> Option<T> {
         self.item_ref().deref()
     }
+}
+
+impl<'a, T, const N: usize> ListItemRef<'a, T, N> {
+    pub fn deref_with<R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        self.item_ref().deref_with(f)
+    }
 
     pub fn remove(&self) -> Option<T> {
-        let guard = &self.guard;
+        let obj = self.item_ref().remove()?;
+        self.reclaim();
+        Some(obj)
+    }
+
+    // Unlink the node once emptied, callers removing through item_ref shall follow up with this
+    pub(crate) fn reclaim(&self) {
         let node_ref = Shared::from(self.node_ptr);
         let node = unsafe { node_ref.deref() };
-        let item_ref = ItemRef {
-            buffer: &node.buffer,
-            idx: self.obj_idx,
+        if node.buffer.peek_back().is_none() {
+            // Internal node is empty, shall remove the node
+            self.list.unlink_empty(node_ref, &self.guard);
+        }
+    }
+
+    // The item next to this one, towards the back
+    pub fn next(&self) -> Option<ListItemRef<'a, T, N>> {
+        self.step(true)
+    }
+
+    // The item next to this one, towards the front
+    pub fn prev(&self) -> Option<ListItemRef<'a, T, N>> {
+        self.step(false)
+    }
+
+    fn step(&self, forwarding: bool) -> Option<ListItemRef<'a, T, N>> {
+        let guard = crossbeam_epoch::pin();
+        let mut node_ptr = self.node_ptr;
+        let node = unsafe { &*node_ptr };
+        let mut item = if forwarding {
+            node.buffer.next_of(self.obj_idx)
+        } else {
+            node.buffer.prev_of(self.obj_idx)
         };
-        if let Some(obj) = item_ref.remove() {
-            if node.buffer.peek_back().is_none() {
-                // Internal node is empty, shall remove the node
-                loop {
-                    let prev_ptr = node.prev.load(Acquire, guard);
-                    let next_ptr = node.next.load(Acquire, guard);
-                    let prev = unsafe { prev_ptr.as_ref() };
-                    let next = unsafe { next_ptr.as_ref() };
-                    let _prev_lock = prev.as_ref().map(|n| n.lock.lock());
-                    let _node_lock = node.lock.lock();
-                    let _next_lock = next.as_ref().map(|n| n.lock.lock());
-                    if prev.map_or(true, |n| n.next.load(Acquire, guard) == node_ref)
-                        && next.map_or(true, |n| n.prev.load(Acquire, guard) == node_ref)
-                    {
-                        prev.map(|n| n.next.store(next_ptr, Release));
-                        next.map(|n| n.prev.store(prev_ptr, Release));
-                        let remains = node.buffer.pop_all();
-                        for v in remains {
-                            // Compensate, order may changed but not much choice here
-                            if prev.is_none() {
-                                // Possibly removing head node
-                                self.list.push_front(v);
-                            } else {
-                                // Possibly removing tail or internal node
-                                self.list.push_back(v);
-                            }
-                        }
-                        unsafe {
-                            guard.defer_destroy(node_ref);
-                        }
-                        break;
-                    }
-                }
+        let (obj_idx, gen) = loop {
+            if let Some(item) = item {
+                break (item.idx, item.gen);
             }
-            Some(obj)
-        } else {
-            None
+            let node = unsafe { &*node_ptr };
+            let next_ref = if forwarding {
+                node.next.load(Acquire, &guard)
+            } else {
+                node.prev.load(Acquire, &guard)
+            };
+            if next_ref.is_null() {
+                return None;
+            }
+            node_ptr = next_ref.as_raw();
+            let next = unsafe { next_ref.deref() };
+            item = if forwarding {
+                next.buffer.peek_front()
+            } else {
+                next.buffer.peek_back()
+            };
+        };
+        Some(ListItemRef {
+            guard,
+            obj_idx,
+            gen,
+            list: self.list,
+            node_ptr,
+        })
+    }
+
+    // The node of the item shall be protected by the guard, takes whatever item is in the slot now
+    pub(crate) unsafe fn from_ptr(
+        list: &'a LinkedRingBufferList<T, N>,
+        ptr: &ItemPtr<T, N>,
+        guard: Guard,
+    ) -> Self {
+        ListItemRef {
+            guard,
+            obj_idx: ptr.idx,
+            gen: (*ptr.buffer).item_at(ptr.idx).gen,
+            list,
+            node_ptr: ptr.buffer as *const RingBufferNode<T, N>,
         }
     }
 
@@ -359,11 +847,87 @@ impl<'a, T: Clone + Default, const N: usize> ListItemRef<'a, T, N> {
         ItemRef {
             buffer: &node.buffer,
             idx: self.obj_idx,
+            gen: self.gen,
+        }
+    }
+
+    pub fn handle(&self) -> ItemHandle<T, N> {
+        self.list.handle_of(&self.item_ref())
+    }
+}
+
+// Moving past either end reaches the ghost position, from where the cursor wraps around
+pub struct ListCursor<'a, T, const N: usize> {
+    list: &'a LinkedRingBufferList<T, N>,
+    current: Option<ListItemRef<'a, T, N>>,
+}
+
+impl<'a, T: Clone, const N: usize> ListCursor<'a, T, N> {
+    pub fn current(&self) -> Option<T> {
+        self.current.as_ref().and_then(|item| item.deref())
+    }
+}
+
+impl<'a, T, const N: usize> ListCursor<'a, T, N> {
+    pub fn item(&self) -> Option<&ListItemRef<'a, T, N>> {
+        self.current.as_ref()
+    }
+
+    pub fn move_next(&mut self) -> bool {
+        self.current = match &self.current {
+            Some(item) => item.next(),
+            None => self.list.peek_front(),
+        };
+        self.current.is_some()
+    }
+
+    pub fn move_prev(&mut self) -> bool {
+        self.current = match &self.current {
+            Some(item) => item.prev(),
+            None => self.list.peek_back(),
+        };
+        self.current.is_some()
+    }
+
+    pub fn seek(&mut self, item: ListItemRef<'a, T, N>) {
+        self.current = Some(item);
+    }
+
+    // Pointers to items behind the current one in its buffer are invalidated, the ghost inserts to the front
+    pub fn insert_after(&self, val: T) -> Result<ItemHandle<T, N>, T> {
+        match &self.current {
+            Some(item) => self.list.insert_after_with(item, val, Self::relocate),
+            None => Ok(self.list.push_front(val)),
+        }
+    }
+
+    // The ghost inserts to the back
+    pub fn insert_before(&self, val: T) -> Result<ItemHandle<T, N>, T> {
+        match &self.current {
+            Some(item) => self.list.insert_before_with(item, val, Self::relocate),
+            None => Ok(self.list.push_back(val)),
+        }
+    }
+
+    // Moves to the next item
+    pub fn remove_current(&mut self) -> Option<T> {
+        self.take_current().and_then(|item| item.remove())
+    }
+
+    pub(crate) fn take_current(&mut self) -> Option<ListItemRef<'a, T, N>> {
+        let item = self.current.take()?;
+        self.current = item.next();
+        Some(item)
+    }
+
+    fn relocate(old: ListItemRef<T, N>, push: &dyn Fn(T) -> ItemHandle<T, N>) {
+        if let Some(v) = old.remove() {
+            push(v);
         }
     }
 }
 
-pub struct ListIter<'a, T: Clone, const N: usize> {
+pub struct ListIter<'a, T, const N: usize> {
     guard: Guard,
     node_ptr: *const RingBufferNode<T, N>,
     list: &'a LinkedRingBufferList<T, N>,
@@ -371,7 +935,7 @@ pub struct ListIter<'a, T: Clone, const N: usize> {
     forwarding: bool,
 }
 
-impl<'a, T: Clone + Default, const N: usize> Iterator for ListIter<'a, T, N> {
+impl<'a, T, const N: usize> Iterator for ListIter<'a, T, N> {
     type Item = ListItemRef<'a, T, N>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -382,6 +946,7 @@ impl<'a, T: Clone + Default, const N: usize> Iterator for ListIter<'a, T, N> {
                 return Some(ListItemRef {
                     guard,
                     obj_idx: item.idx,
+                    gen: item.gen,
                     list: self.list,
                     node_ptr: self.node_ptr,
                 });
@@ -410,7 +975,32 @@ impl<'a, T: Clone + Default, const N: usize> Iterator for ListIter<'a, T, N> {
     }
 }
 
-unsafe impl<T: Clone + Default, const N: usize> Send for LinkedRingBufferList<T, N> {}
+impl<T, const N: usize> NodePool<T, N> {
+    fn put(&self, node_ptr: *const RingBufferNode<T, N>) {
+        self.nodes.lock().push(node_ptr as *mut _);
+    }
+
+    fn take(&self) -> Option<*mut RingBufferNode<T, N>> {
+        self.nodes.lock().pop()
+    }
+}
+
+impl<T, const N: usize> Drop for NodePool<T, N> {
+    fn drop(&mut self) {
+        for node_ptr in self.nodes.get_mut().drain(..) {
+            unsafe {
+                drop(Box::from_raw(node_ptr));
+            }
+        }
+    }
+}
+
+// The pool only hands over whole nodes, items are never reached through it
+unsafe impl<T: Send, const N: usize> Send for NodePool<T, N> {}
+unsafe impl<T: Send, const N: usize> Sync for NodePool<T, N> {}
+
+// Shared lists are Sync as their nodes are, with items moved across and read in place
+unsafe impl<T: Send, const N: usize> Send for LinkedRingBufferList<T, N> {}
 
 impl<T, const N: usize> Drop for LinkedRingBufferList<T, N> {
     fn drop(&mut self) {
@@ -528,6 +1118,154 @@ mod test {
         debug_assert!(list.peek_back().is_none());
     }
 
+    #[test]
+    pub fn cursor() {
+        // Three items per buffer, inserting in the middle splits buffers
+        let list = LinkedRingBufferList::<usize, 4>::new();
+        for i in 0..9 {
+            list.push_back(i);
+        }
+        let items = |list: &LinkedRingBufferList<usize, 4>| {
+            list.iter_front()
+                .filter_map(|r| r.deref())
+                .collect::<Vec<_>>()
+        };
+        let mut cursor = list.cursor_front();
+        assert_eq!(cursor.current(), Some(0));
+        for i in 1..9 {
+            assert!(cursor.move_next());
+            assert_eq!(cursor.current(), Some(i));
+        }
+        assert!(!cursor.move_next());
+        assert!(cursor.move_prev());
+        assert_eq!(cursor.current(), Some(8));
+        while cursor.current() != Some(4) {
+            cursor.move_prev();
+        }
+        cursor.insert_after(40).ok().unwrap();
+        cursor.insert_before(30).ok().unwrap();
+        assert_eq!(items(&list), vec![0, 1, 2, 3, 30, 4, 40, 5, 6, 7, 8]);
+        assert!(cursor.move_next());
+        assert_eq!(cursor.current(), Some(40));
+        assert_eq!(cursor.remove_current(), Some(40));
+        assert_eq!(cursor.current(), Some(5));
+        assert_eq!(cursor.remove_current(), Some(5));
+        cursor.insert_before(50).ok().unwrap();
+        assert_eq!(items(&list), vec![0, 1, 2, 3, 30, 4, 50, 6, 7, 8]);
+        let mut back = list.cursor_back();
+        let mut reversed = vec![];
+        while let Some(v) = back.current() {
+            reversed.push(v);
+            back.move_prev();
+        }
+        reversed.reverse();
+        assert_eq!(reversed, items(&list));
+        for i in (0..10).rev() {
+            assert!(list.pop_front().is_some(), "{}", i);
+        }
+        assert_eq!(list.pop_back(), None);
+    }
+
+    #[test]
+    pub fn handles() {
+        let list = LinkedRingBufferList::<usize, 4>::new();
+        let handles = (0..12).map(|i| list.push_back(i)).collect::<Vec<_>>();
+        assert_eq!(list.resolve(&handles[5]).unwrap().deref(), Some(5));
+        assert_eq!(list.remove_handle(&handles[5]), Some(5));
+        assert_eq!(list.remove_handle(&handles[5]), None);
+        // Drained nodes go back to the pool and get filled again, old handles stay stale
+        for i in (0..12).filter(|i| *i != 5) {
+            assert_eq!(list.pop_front(), Some(i));
+        }
+        for i in 0..12 {
+            list.push_back(i + 100);
+        }
+        assert!(handles.iter().all(|h| list.resolve(h).is_none()));
+        let other = LinkedRingBufferList::<usize, 4>::new();
+        let handle = list.push_front(42);
+        assert!(other.resolve(&handle).is_none());
+        assert_eq!(list.resolve(&handle).unwrap().deref(), Some(42));
+    }
+
+    #[test]
+    pub fn batch() {
+        // Runs span several buffers of three
+        let list = LinkedRingBufferList::<usize, 4>::new();
+        assert_eq!(list.push_many(0..20), 20);
+        list.push_front(100);
+        let mut out = vec![];
+        assert_eq!(list.pop_many(&mut out, 5), 5);
+        assert_eq!(out, vec![100, 0, 1, 2, 3]);
+        assert_eq!(list.push_many((20..30).filter(|i| i % 2 == 0)), 5);
+        assert_eq!(list.pop_many(&mut out, 100), 21);
+        assert_eq!(
+            out[5..],
+            (4..20).chain((20..30).step_by(2)).collect::<Vec<_>>()[..]
+        );
+        assert_eq!(list.pop_many(&mut out, 100), 0);
+        assert!(list.peek_front().is_none());
+    }
+
+    #[test]
+    pub fn par_handles() {
+        // Pops racing pushes at the same end never move items to other nodes, handles stay good
+        let list = Arc::new(LinkedRingBufferList::<usize, 4>::new());
+        let num = 20480;
+        let pushers = (0..2)
+            .map(|t| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    (t * num..(t + 1) * num)
+                        .map(|i| (i, list.push_front(i)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let popper = {
+            let list = list.clone();
+            thread::spawn(move || {
+                (0..num)
+                    .filter_map(|_| list.pop_front())
+                    .collect::<HashSet<_>>()
+            })
+        };
+        let pushed = pushers
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect::<Vec<_>>();
+        let popped = popper.join().unwrap();
+        for (i, handle) in pushed {
+            if !popped.contains(&i) {
+                assert_eq!(list.resolve(&handle).unwrap().deref(), Some(i));
+            }
+        }
+    }
+
+    #[test]
+    pub fn par_insert_at_back() {
+        // Splitting the back node races pushes extending the back, inserting never fails for it
+        let list = Arc::new(LinkedRingBufferList::<usize, 4>::new());
+        list.push_back(0);
+        let num = 4096;
+        let pusher = {
+            let list = list.clone();
+            thread::spawn(move || {
+                for i in 1..=num {
+                    list.push_back(i);
+                }
+            })
+        };
+        let cursor = list.cursor_front();
+        for i in 1..=num {
+            assert!(cursor.insert_after(num + i).is_ok(), "{}", i);
+        }
+        pusher.join().unwrap();
+        assert_eq!(
+            list.iter_front().filter_map(|r| r.deref()).count(),
+            num * 2 + 1
+        );
+    }
+
     const NUM: usize = 409600;
     const CAP: usize = 128;
 
@@ -1,19 +1,82 @@
 use crate::{
-    linked_map::{KVPair, LinkedHashMap},
+    cache_metrics::{CacheMetrics, MetricsSnapshot, RemovalCause, RemovalListener},
+    codec::{decode_len, encode_len, Codec},
+    flight::{yield_now, Flight, FLIGHTS_CAP},
+    linked_map::{KVPair, LinkedHashMap, Reservation},
     list::ListIter,
+    map::{Map, PtrHashMap},
 };
-use std::hash::Hash;
+use std::{
+    any::Any,
+    convert::Infallible,
+    future::Future,
+    hash::Hash,
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering::*},
+        Arc,
+    },
+    time::Instant,
+};
+
+// Initial map capacity for weighted caches, the budget says nothing about the number of entries
+const WEIGHTED_MAP_CAP: usize = 64;
+
+pub type Weigher<K, V> = Box<dyn Fn(&K, &V) -> usize + Send + Sync>;
+
+// What a fetch got, shared with the misses waiting on it
+#[derive(Clone)]
+enum Fetched<V> {
+    Value(V),
+    Nothing,
+    Failed(Arc<dyn Any + Send + Sync>),
+}
 
-pub struct LRUCache<K: Clone + Hash + Eq + Default, V: Clone + Default, const N: usize> {
+type Flights<K, V> = PtrHashMap<K, Arc<Flight<Fetched<V>>>>;
+
+pub struct LRUCache<K: Clone + Hash + Eq, V: Clone, const N: usize> {
     map: LinkedHashMap<K, V, N>,
-    capacity: usize,
+    // Fetches in flight, put in before reserving the key and taken out after the reservation is
+    // filled or given up
+    flights: Flights<K, V>,
+    capacity: AtomicUsize,
+    weigher: Option<Weigher<K, V>>,
+    weight: AtomicUsize,
+    metrics: CacheMetrics,
+    listener: Option<RemovalListener<K, V>>,
 }
 
-impl<K: Clone + Hash + Eq + Default, V: Clone + Default, const N: usize> LRUCache<K, V, N> {
+impl<K: Clone + Hash + Eq, V: Clone, const N: usize> LRUCache<K, V, N> {
     pub fn new(capacity: usize) -> LRUCache<K, V, N> {
+        Self::build(capacity, capacity, None)
+    }
+
+    // Bound the cache by the total weight of the entries instead of the number of them
+    pub fn with_weigher<W: Fn(&K, &V) -> usize + Send + Sync + 'static>(
+        max_weight: usize,
+        weigher: W,
+    ) -> LRUCache<K, V, N> {
+        Self::build(WEIGHTED_MAP_CAP, max_weight, Some(Box::new(weigher)))
+    }
+
+    // Called with every entry leaving the cache, along with the reason
+    pub fn with_removal_listener<L: Fn(&K, &V, RemovalCause) + Send + Sync + 'static>(
+        mut self,
+        listener: L,
+    ) -> Self {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    fn build(map_cap: usize, capacity: usize, weigher: Option<Weigher<K, V>>) -> Self {
         Self {
-            map: LinkedHashMap::with_capacity(capacity),
-            capacity,
+            map: LinkedHashMap::with_capacity(map_cap),
+            flights: PtrHashMap::with_capacity(FLIGHTS_CAP),
+            capacity: AtomicUsize::new(capacity),
+            weigher,
+            weight: AtomicUsize::new(0),
+            metrics: CacheMetrics::new(),
+            listener: None,
         }
     }
 
@@ -23,33 +86,643 @@ impl<K: Clone + Hash + Eq + Default, V: Clone + Default, const N: usize> LRUCach
         fetch_fn: FF,
         evict_fn: EF,
     ) -> Option<V> {
-        let res = self.map.get_to_front(key);
-        if res.is_none() {
-            if let Some(v) = fetch_fn(key) {
-                self.map.insert_front(key.clone(), v.clone());
-                while self.map.len() > self.capacity {
-                    if let Some(evic) = self.map.pop_back() {
-                        evict_fn(evic.0, evic.1)
+        match self.try_get::<Infallible, _, _>(key, move |k| Ok(fetch_fn(k)), evict_fn) {
+            Ok(v) => v,
+            Err(e) => match e {},
+        }
+    }
+
+    // Concurrent misses on the same key are coalesced, only one of them calls the fetch function
+    // and the others get what it got, be it a value, nothing or an error. Those expecting errors
+    // of another type fetch by themselves once it is done, and so do misses on a key loaded by a
+    // future on the same thread, blocking would stall that future
+    pub fn try_get<E, FF, EF>(&self, key: &K, fetch_fn: FF, evict_fn: EF) -> Result<Option<V>, E>
+    where
+        E: Clone + Send + Sync + 'static,
+        FF: Fn(&K) -> Result<Option<V>, E> + Send,
+        EF: Fn(K, V) + Send,
+    {
+        let mut missed = false;
+        loop {
+            if let Some(flight) = self.flights.get(key) {
+                if !flight.can_wait() {
+                    if !missed {
+                        self.metrics.record_miss();
+                    }
+                    let start = Instant::now();
+                    let res = fetch_fn(key);
+                    self.metrics.record_load(start.elapsed(), res.is_ok());
+                    return res;
+                }
+                match flight.wait() {
+                    Some(Fetched::Value(v)) => {
+                        if !missed {
+                            self.metrics.record_hit();
+                        }
+                        return Ok(Some(v));
+                    }
+                    Some(Fetched::Nothing) => {
+                        if !missed {
+                            self.metrics.record_miss();
+                        }
+                        return Ok(None);
+                    }
+                    Some(Fetched::Failed(e)) => {
+                        if let Some(e) = e.downcast_ref::<E>() {
+                            if !missed {
+                                self.metrics.record_miss();
+                            }
+                            return Err(e.clone());
+                        }
+                    }
+                    None => {}
+                }
+                // Given up, its flight is gone already
+                continue;
+            }
+            if let Some(v) = self.map.get_to_front(key) {
+                if !missed {
+                    self.metrics.record_hit();
+                }
+                return Ok(Some(v));
+            }
+            if !missed {
+                self.metrics.record_miss();
+                missed = true;
+            }
+            let leading = match self.lead(key, false) {
+                Some(leading) => leading,
+                // Another miss is fetching it, wait for its flight
+                None => continue,
+            };
+            let reservation = match self.map.reserve(key) {
+                Some(reservation) => reservation,
+                // Cached meanwhile
+                None => continue,
+            };
+            let start = Instant::now();
+            let res = fetch_fn(key);
+            self.metrics.record_load(start.elapsed(), res.is_ok());
+            leading.land(&res);
+            if let Ok(Some(v)) = &res {
+                self.fill(key, reservation, v.clone(), &evict_fn);
+            }
+            return res;
+        }
+    }
+
+    // Same as get, evictions are only reported to the removal listener. Futures never block on the
+    // placeholder entry of the key, they are woken once its fetch lands
+    pub async fn get_async<F: Future<Output = Option<V>>, L: Fn(&K) -> F>(
+        &self,
+        key: &K,
+        loader: L,
+    ) -> Option<V> {
+        let mut missed = false;
+        loop {
+            if let Some(flight) = self.flights.get(key) {
+                match flight.landed().await {
+                    Some(Fetched::Value(v)) => {
+                        if !missed {
+                            self.metrics.record_hit();
+                        }
+                        return Some(v);
+                    }
+                    Some(Fetched::Nothing) => {
+                        if !missed {
+                            self.metrics.record_miss();
+                        }
+                        return None;
                     }
+                    // Given up, or failed with an error only its own caller can take
+                    _ => continue,
+                }
+            }
+            if self.map.is_reserved(key) {
+                // Landed and about to be filled, there is nothing left to wait on
+                yield_now().await;
+                continue;
+            }
+            if let Some(v) = self.map.get_to_front(key) {
+                if !missed {
+                    self.metrics.record_hit();
                 }
                 return Some(v);
-            } else {
-                return None;
             }
+            if !missed {
+                self.metrics.record_miss();
+                missed = true;
+            }
+            let leading = match self.lead(key, true) {
+                Some(leading) => leading,
+                None => continue,
+            };
+            let reservation = match self.map.reserve(key) {
+                Some(reservation) => reservation,
+                None => continue,
+            };
+            let start = Instant::now();
+            let res = loader(key).await;
+            self.metrics.record_load(start.elapsed(), true);
+            leading.land(&Ok::<_, Infallible>(res.clone()));
+            if let Some(v) = &res {
+                self.fill(key, reservation, v.clone(), |_, _| {});
+            }
+            return res;
         }
-        res
+    }
+
+    pub fn put<EF: Fn(K, V)>(&self, key: K, value: V, evict_fn: EF) -> Option<V> {
+        let weight = self.weigh(&key, &value);
+        if weight > self.capacity() {
+            // Never fits, caching it would only flush everything else
+            return self.remove_by(&key, RemovalCause::Size);
+        }
+        self.weight.fetch_add(weight, AcqRel);
+        let old = self.map.insert_front(key.clone(), value);
+        if let Some(old) = &old {
+            self.removed(&key, old, RemovalCause::Replaced);
+        }
+        self.evict_to_capacity(&evict_fn);
+        old
+    }
+
+    fn lead<'a>(&'a self, key: &'a K, led_async: bool) -> Option<Leading<'a, K, V>> {
+        let flight = Arc::new(Flight::new(led_async));
+        if self
+            .flights
+            .try_insert(key.clone(), flight.clone())
+            .is_some()
+        {
+            return None;
+        }
+        Some(Leading {
+            flights: &self.flights,
+            key,
+            flight,
+            landed: false,
+        })
+    }
+
+    // Weighed as put does, except there is no old value to replace
+    fn fill<EF: Fn(K, V)>(
+        &self,
+        key: &K,
+        reservation: Reservation<K, V, N>,
+        value: V,
+        evict_fn: EF,
+    ) {
+        let weight = self.weigh(key, &value);
+        if weight > self.capacity() {
+            // Never fits, the reservation is given up on drop
+            return;
+        }
+        self.weight.fetch_add(weight, AcqRel);
+        reservation.fill(value);
+        self.evict_to_capacity(&evict_fn);
+    }
+
+    // Read without promoting the entry
+    pub fn peek(&self, key: &K) -> Option<V> {
+        self.map.get(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
     }
 
     pub fn remove(&self, key: &K) -> Option<V> {
-        self.map.remove(key)
+        self.remove_by(key, RemovalCause::Explicit)
+    }
+
+    pub fn pop_lru(&self) -> Option<(K, V)> {
+        self.pop_lru_by(RemovalCause::Explicit)
+    }
+
+    pub fn clear(&self) {
+        while self.pop_lru().is_some() {}
+    }
+
+    pub fn set_capacity<EF: Fn(K, V)>(&self, capacity: usize, evict_fn: EF) {
+        self.capacity.store(capacity, Release);
+        self.evict_to_capacity(&evict_fn);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity.load(Acquire)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn weight(&self) -> usize {
+        self.weight.load(Acquire)
+    }
+
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
     }
 
     pub fn iter(&self) -> ListIter<KVPair<K, V>, N> {
         self.map.iter_front()
     }
+
+    #[inline(always)]
+    fn evict_to_capacity<EF: Fn(K, V)>(&self, evict_fn: &EF) {
+        while self.weight() > self.capacity() {
+            if let Some((k, v)) = self.pop_lru_by(RemovalCause::Size) {
+                evict_fn(k, v)
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn remove_by(&self, key: &K, cause: RemovalCause) -> Option<V> {
+        let res = self.map.remove(key);
+        if let Some(v) = &res {
+            self.removed(key, v, cause);
+        }
+        res
+    }
+
+    fn pop_lru_by(&self, cause: RemovalCause) -> Option<(K, V)> {
+        self.map.pop_back().map(|KVPair(k, v)| {
+            self.removed(&k, &v, cause);
+            (k, v)
+        })
+    }
+
+    #[inline(always)]
+    fn removed(&self, key: &K, value: &V, cause: RemovalCause) {
+        self.weight.fetch_sub(self.weigh(key, value), AcqRel);
+        self.metrics.record_removal(cause);
+        if let Some(listener) = &self.listener {
+            listener(key, value, cause);
+        }
+    }
+
+    #[inline(always)]
+    fn weigh(&self, key: &K, value: &V) -> usize {
+        self.weigher
+            .as_ref()
+            .map_or(1, |weigher| weigher(key, value))
+    }
+}
+
+// Leads the fetch of a key, its flight is given up on drop unless landed
+struct Leading<'a, K: Clone + Hash + Eq, V: Clone> {
+    flights: &'a Flights<K, V>,
+    key: &'a K,
+    flight: Arc<Flight<Fetched<V>>>,
+    landed: bool,
+}
+
+impl<'a, K: Clone + Hash + Eq, V: Clone> Leading<'a, K, V> {
+    // Before the reservation is filled or given up. The flight is taken out first, so the waiters
+    // going on never find it again, and those coming later wait on the placeholder for a moment
+    fn land<E: Clone + Send + Sync + 'static>(mut self, res: &Result<Option<V>, E>) {
+        self.flights.remove(self.key);
+        self.flight.land(Some(match res {
+            Ok(Some(v)) => Fetched::Value(v.clone()),
+            Ok(None) => Fetched::Nothing,
+            Err(e) => Fetched::Failed(Arc::new(e.clone())),
+        }));
+        self.landed = true;
+    }
+}
+
+impl<'a, K: Clone + Hash + Eq, V: Clone> Drop for Leading<'a, K, V> {
+    fn drop(&mut self) {
+        if !self.landed {
+            self.flights.remove(self.key);
+            self.flight.land(None);
+        }
+    }
+}
+
+impl<K: Clone + Hash + Eq + Codec, V: Clone + Codec, const N: usize> LRUCache<K, V, N> {
+    // Write all entries from the most recently used to the least, for restoring later
+    pub fn dump<W: Write>(&self, writer: &mut W) -> io::Result<usize> {
+        let entries = self.iter().filter_map(|p| p.deref()).collect::<Vec<_>>();
+        encode_len(entries.len(), writer)?;
+        for KVPair(k, v) in &entries {
+            k.encode(writer)?;
+            v.encode(writer)?;
+        }
+        Ok(entries.len())
+    }
+
+    pub fn restore<R: Read>(capacity: usize, reader: &mut R) -> io::Result<Self> {
+        let cache = Self::new(capacity);
+        cache.restore_from(reader)?;
+        Ok(cache)
+    }
+
+    // Put the dumped entries back with their recency order, the least recent ones evicted if over capacity
+    pub fn restore_from<R: Read>(&self, reader: &mut R) -> io::Result<usize> {
+        let entries = (0..decode_len(reader)?)
+            .map(|_| Ok((K::decode(reader)?, V::decode(reader)?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let num = entries.len();
+        for (k, v) in entries.into_iter().rev() {
+            self.put(k, v, |_, _| {});
+        }
+        Ok(num)
+    }
 }
 
-unsafe impl<K: Clone + Hash + Eq + Default, V: Clone + Default, const N: usize> Send
+unsafe impl<K: Clone + Hash + Eq + Send, V: Clone + Send, const N: usize> Send
     for LRUCache<K, V, N>
 {
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::flight::{flag_waker, run_local, YieldNow};
+    use std::{
+        pin::Pin,
+        sync::{Arc, Barrier, Mutex},
+        task::{Context, Poll},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    pub fn weighted() {
+        let cache = LRUCache::<usize, Vec<u8>, 16>::with_weigher(1000, |_, v| v.len());
+        let evicted = AtomicUsize::new(0);
+        for i in 0..10 {
+            cache.get(
+                &i,
+                |_| Some(vec![0; 300]),
+                |_, _| {
+                    evicted.fetch_add(1, Relaxed);
+                },
+            );
+            assert!(cache.weight() <= 1000);
+        }
+        assert_eq!(cache.weight(), 900);
+        assert_eq!(evicted.load(Relaxed), 7);
+        // Most recent entries survive
+        assert_eq!(
+            cache.get(&9, |_| None, |_, _| {}).map(|v| v.len()),
+            Some(300)
+        );
+        assert_eq!(cache.get(&0, |_| None, |_, _| {}), None);
+        // Heavier than the budget, returned but not cached
+        assert_eq!(
+            cache
+                .get(&100, |_| Some(vec![0; 2000]), |_, _| {})
+                .map(|v| v.len()),
+            Some(2000)
+        );
+        assert_eq!(cache.get(&100, |_| None, |_, _| {}), None);
+        assert_eq!(cache.weight(), 900);
+        cache.remove(&9);
+        assert_eq!(cache.weight(), 600);
+    }
+
+    #[test]
+    pub fn operations() {
+        let cache = LRUCache::<usize, usize, 16>::new(8);
+        let evicted = AtomicUsize::new(0);
+        let evict_fn = |_, _| {
+            evicted.fetch_add(1, Relaxed);
+        };
+        for i in 0..8 {
+            assert_eq!(cache.put(i, i * 10, evict_fn), None);
+        }
+        assert_eq!(cache.len(), 8);
+        assert_eq!(cache.put(0, 1, evict_fn), Some(0));
+        assert!(cache.contains_key(&1));
+        // Peek does not promote, 1 is still the least recent one
+        assert_eq!(cache.peek(&1), Some(10));
+        assert_eq!(cache.pop_lru(), Some((1, 10)));
+        assert!(!cache.contains_key(&1));
+        assert_eq!(cache.peek(&1), None);
+        cache.set_capacity(4, evict_fn);
+        assert_eq!(cache.len(), 4);
+        assert_eq!(evicted.load(Relaxed), 3);
+        assert_eq!(cache.capacity(), 4);
+        let keys = cache
+            .iter()
+            .filter_map(|p| p.deref().map(|p| p.0))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![0, 7, 6, 5]);
+        cache.put(100, 100, evict_fn);
+        assert_eq!(cache.len(), 4);
+        assert_eq!(evicted.load(Relaxed), 4);
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.weight(), 0);
+        assert_eq!(cache.peek(&0), None);
+    }
+
+    #[test]
+    pub fn single_flight() {
+        let cache = Arc::new(LRUCache::<usize, usize, 16>::new(64));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let failures = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+        let threads = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                let fetches = fetches.clone();
+                let failures = failures.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    let ok = cache.get(
+                        &1,
+                        |k| {
+                            fetches.fetch_add(1, Relaxed);
+                            thread::sleep(Duration::from_millis(200));
+                            Some(k * 10)
+                        },
+                        |_, _| {},
+                    );
+                    barrier.wait();
+                    // Failures are shared as well
+                    let err = cache.try_get(
+                        &2,
+                        |_| {
+                            failures.fetch_add(1, Relaxed);
+                            thread::sleep(Duration::from_millis(200));
+                            Err("backend down")
+                        },
+                        |_, _| {},
+                    );
+                    barrier.wait();
+                    let none = cache.get(
+                        &3,
+                        |_| {
+                            fetches.fetch_add(1, Relaxed);
+                            thread::sleep(Duration::from_millis(200));
+                            None
+                        },
+                        |_, _| {},
+                    );
+                    (ok, err, none)
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            assert_eq!(t.join().unwrap(), (Some(10), Err("backend down"), None));
+        }
+        assert_eq!(fetches.load(Relaxed), 2);
+        assert_eq!(failures.load(Relaxed), 1);
+        assert_eq!(cache.len(), 1);
+        assert!(!cache.contains_key(&2));
+        assert!(!cache.contains_key(&3));
+        // One hit or miss for each call, however long it waited
+        let metrics = cache.metrics();
+        assert_eq!(metrics.hits + metrics.misses, 24);
+        assert!(metrics.misses > 16);
+    }
+
+    #[test]
+    pub fn metrics_and_listener() {
+        let removed = Arc::new(Mutex::new(vec![]));
+        let cache = {
+            let removed = removed.clone();
+            LRUCache::<usize, usize, 16>::new(4).with_removal_listener(move |k, v, cause| {
+                removed.lock().unwrap().push((*k, *v, cause))
+            })
+        };
+        for i in 0..6 {
+            cache.get(&i, |k| Some(k * 10), |_, _| {});
+        }
+        cache.get(&5, |_| None, |_, _| {});
+        assert_eq!(cache.try_get(&9, |_| Err(()), |_, _| {}), Err(()));
+        cache.remove(&5);
+        // The old value leaves the cache as well
+        assert_eq!(cache.put(4, 41, |_, _| {}), Some(40));
+        assert_eq!(cache.weight(), 3);
+        let metrics = cache.metrics();
+        assert_eq!(metrics.hits, 1);
+        assert_eq!(metrics.misses, 7);
+        assert_eq!(metrics.loads, 6);
+        assert_eq!(metrics.load_failures, 1);
+        assert_eq!(metrics.size_evictions, 2);
+        assert_eq!(metrics.explicit_removals, 1);
+        assert_eq!(metrics.replacements, 1);
+        assert_eq!(
+            *removed.lock().unwrap(),
+            vec![
+                (0, 0, RemovalCause::Size),
+                (1, 10, RemovalCause::Size),
+                (5, 50, RemovalCause::Explicit),
+                (4, 40, RemovalCause::Replaced)
+            ]
+        );
+    }
+
+    #[test]
+    pub fn get_async() {
+        let cache = LRUCache::<usize, usize, 16>::new(64);
+        let loads = AtomicUsize::new(0);
+        let loader = |k: &usize| {
+            let k = *k;
+            let loads = &loads;
+            async move {
+                loads.fetch_add(1, Relaxed);
+                YieldNow(false).await;
+                Some(k * 10)
+            }
+        };
+        let keys = [0, 1];
+        let gets = (0..8)
+            .map(|i| {
+                Box::pin(cache.get_async(&keys[i % 2], loader)) as Pin<Box<dyn Future<Output = _>>>
+            })
+            .collect();
+        assert_eq!(run_local(gets), vec![Some(0usize), Some(10)].repeat(4));
+        assert_eq!(loads.load(Relaxed), 2);
+        assert_eq!(cache.len(), 2);
+        let gets = vec![Box::pin(cache.get_async(&1, loader)) as Pin<Box<dyn Future<Output = _>>>];
+        assert_eq!(run_local(gets), vec![Some(10usize)]);
+        assert_eq!(loads.load(Relaxed), 2);
+    }
+
+    #[test]
+    pub fn async_waiters_are_woken() {
+        let cache = Arc::new(LRUCache::<usize, usize, 16>::new(8));
+        let started = Arc::new(Barrier::new(2));
+        let release = Arc::new(Barrier::new(2));
+        let leader = {
+            let (cache, started, release) = (cache.clone(), started.clone(), release.clone());
+            thread::spawn(move || {
+                cache.get(
+                    &1,
+                    |k| {
+                        started.wait();
+                        release.wait();
+                        Some(k * 10)
+                    },
+                    |_, _| {},
+                )
+            })
+        };
+        started.wait();
+        let (woken, waker) = flag_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut waiter = Box::pin(cache.get_async(&1, |_| async { panic!("should wait") }));
+        // Not asking to be polled again until the fetch lands
+        assert!(waiter.as_mut().poll(&mut cx).is_pending());
+        assert!(!woken.load(SeqCst));
+        release.wait();
+        assert_eq!(leader.join().unwrap(), Some(10));
+        assert!(woken.load(SeqCst));
+        assert_eq!(waiter.as_mut().poll(&mut cx), Poll::Ready(Some(10)));
+    }
+
+    #[test]
+    pub fn sync_miss_on_async_load() {
+        // Blocking on the future would hang the only thread polling it, the get fetches by itself
+        let cache = LRUCache::<usize, usize, 16>::new(8);
+        let gets = vec![
+            Box::pin(cache.get_async(&1, |k: &usize| {
+                let k = *k;
+                async move {
+                    YieldNow(false).await;
+                    Some(k * 10)
+                }
+            })) as Pin<Box<dyn Future<Output = _>>>,
+            Box::pin(async { cache.get(&1, |k| Some(k * 20), |_, _| {}) }),
+        ];
+        assert_eq!(run_local(gets), vec![Some(10usize), Some(20)]);
+        assert_eq!(cache.peek(&1), Some(10));
+        assert_eq!(cache.metrics().loads, 2);
+    }
+
+    #[test]
+    pub fn dump_and_restore() {
+        let cache = LRUCache::<usize, String, 16>::new(8);
+        for i in 0..8 {
+            cache.put(i, i.to_string(), |_, _| {});
+        }
+        cache.get(&2, |_| None, |_, _| {});
+        let mut buf = vec![];
+        assert_eq!(cache.dump(&mut buf).unwrap(), 8);
+        let keys = |cache: &LRUCache<usize, String, 16>| {
+            cache
+                .iter()
+                .filter_map(|p| p.deref().map(|p| p.0))
+                .collect::<Vec<_>>()
+        };
+        let restored = LRUCache::<usize, String, 16>::restore(8, &mut &buf[..]).unwrap();
+        assert_eq!(keys(&restored), keys(&cache));
+        assert_eq!(restored.peek(&5), Some("5".to_string()));
+        // Only the most recent entries survive a smaller cache
+        let smaller = LRUCache::<usize, String, 16>::restore(4, &mut &buf[..]).unwrap();
+        assert_eq!(keys(&smaller), vec![2, 7, 6, 5]);
+        assert!(LRUCache::<usize, String, 16>::restore(8, &mut &buf[..20]).is_err());
+    }
+}
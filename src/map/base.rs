@@ -99,7 +99,6 @@ pub struct ChunkPtr<K, V, A: Attachment<K, V>, ALLOC: GlobalAlloc + Default> {
 pub struct Table<K, V, A: Attachment<K, V>, ALLOC: GlobalAlloc + Default, H: Hasher + Default> {
     meta: Arc<ChunkMeta<K, V, A, ALLOC>>,
     attachment_init_meta: A::InitMeta,
-    count: AtomicUsize,
     init_cap: usize,
     mark: PhantomData<H>,
 }
@@ -108,6 +107,8 @@ struct ChunkMeta<K, V, A: Attachment<K, V>, ALLOC: GlobalAlloc + Default> {
     new_chunk: Atomic<ChunkPtr<K, V, A, ALLOC>>,
     pub chunk: Atomic<ChunkPtr<K, V, A, ALLOC>>,
     epoch: AtomicUsize,
+    // Shared with the migration thread, which drops old entries already put in the new chunk
+    count: AtomicUsize,
 }
 
 impl<
@@ -134,8 +135,8 @@ impl<
                 chunk: Atomic::new(ChunkPtr::new(chunk)),
                 new_chunk: Atomic::null(),
                 epoch: AtomicUsize::new(0),
+                count: AtomicUsize::new(0),
             }),
-            count: AtomicUsize::new(0),
             init_cap: cap,
             attachment_init_meta,
             mark: PhantomData,
@@ -286,10 +287,12 @@ impl<
             let value_insertion =
                 self.modify_entry(&*modify_chunk, hash, key, fkey, mod_op, true, &guard, None);
             let mut result = None;
+            let mut counted = false;
             match value_insertion {
                 ModResult::Done(_, _, _) => {
                     modify_chunk.occupation.fetch_add(1, Relaxed);
-                    self.count.fetch_add(1, Relaxed);
+                    self.meta.count.fetch_add(1, Relaxed);
+                    counted = true;
                 }
                 ModResult::Replaced(fv, v, _) | ModResult::Existed(fv, v) => {
                     result = Some((fv, v.unwrap()))
@@ -381,6 +384,10 @@ impl<
                 match (&old_val, &result) {
                     (ModResult::Replaced(fv, v, _), None) | (ModResult::Existed(fv, v), None) => {
                         if *fv > NUM_FIX_V {
+                            if counted {
+                                // The key was still alive in the old chunk, not a new entry
+                                self.meta.count.fetch_sub(1, Relaxed);
+                            }
                             result = Some((*fv, v.clone().unwrap()))
                         }
                     }
@@ -425,7 +432,7 @@ impl<
                 .store(owned_new.into_shared(&guard), Release);
             self.meta.new_chunk.store(Shared::null(), Release);
             dfence();
-            self.count.fetch_sub(len, AcqRel);
+            self.meta.count.fetch_sub(len, AcqRel);
             break;
         }
     }
@@ -545,7 +552,7 @@ impl<
     pub fn remove(&self, key: &K, fkey: FKey) -> Option<(FVal, V)> {
         let tagging_res = self.insert(InsertOp::Tombstone, key, None, fkey, TOMBSTONE_VALUE);
         if tagging_res.is_some() {
-            self.count.fetch_sub(1, AcqRel);
+            self.meta.count.fetch_sub(1, AcqRel);
         }
         return tagging_res;
     }
@@ -608,7 +615,7 @@ impl<
     }
 
     pub fn len(&self) -> usize {
-        self.count.load(Relaxed)
+        self.meta.count.load(Relaxed)
     }
 
     fn get_from_chunk(
@@ -770,7 +777,9 @@ impl<
                                             if Self::FAT_VAL {
                                                 Self::store_raw_value(addr, val_to_store);
                                             }
-                                            return ModResult::Replaced(act_val, prev_val, idx);
+                                            // Reviving a tombstone is a new entry, same as insert
+                                            chunk.empty_entries.fetch_sub(1, Relaxed);
+                                            return ModResult::Done(act_val, prev_val, idx);
                                         } else {
                                             if Self::FAT_VAL && read_attachment {
                                                 // Fast value changed, cannot obtain stable fat value
@@ -1055,14 +1064,65 @@ impl<
         return res;
     }
 
+    fn sample_from_chunk<R, F: Fn(&V) -> R>(
+        &self,
+        chunk: &Chunk<K, V, A, ALLOC>,
+        start: usize,
+        max: usize,
+        f: &F,
+        res: &mut Vec<(FVal, K, R)>,
+    ) {
+        let mut idx = start;
+        let cap = chunk.capacity;
+        let mut counter = 0;
+        let cap_mask = chunk.cap_mask();
+        while counter < cap && res.len() < max {
+            idx &= cap_mask;
+            let addr = chunk.entry_addr(idx);
+            let k = Self::get_fast_key(addr);
+            if k != EMPTY_KEY {
+                let val_res = Self::get_fast_value(addr);
+                let act_val = val_res.act_val::<V>();
+                if act_val >= NUM_FIX_V {
+                    let attachment = chunk.attachment.prefetch(idx);
+                    let key = attachment.get_key();
+                    let seen = attachment.with_value(f);
+                    // Changed while looking, not worth a retry for a sample
+                    if Self::get_fast_value(addr).val == val_res.val {
+                        res.push((act_val, key, seen));
+                    }
+                }
+            }
+            idx += 1; // reprobe
+            counter += 1;
+        }
+    }
+
+    // Up to `max` live entries from the slot at `start` on, wrapping around. Keys are cloned,
+    // values are only seen through `f`
+    pub fn sample<R, F: Fn(&V) -> R>(&self, start: usize, max: usize, f: F) -> Vec<(FVal, K, R)> {
+        let guard = crossbeam_epoch::pin();
+        let old_chunk_ref = self.meta.chunk.load(Acquire, &guard);
+        let new_chunk_ref = self.meta.new_chunk.load(Acquire, &guard);
+        let old_chunk = unsafe { old_chunk_ref.deref() };
+        let mut res = vec![];
+        self.sample_from_chunk(&*old_chunk, start, max, &f, &mut res);
+        if !new_chunk_ref.is_null() && old_chunk_ref != new_chunk_ref {
+            let new_chunk = unsafe { new_chunk_ref.deref() };
+            self.sample_from_chunk(&*new_chunk, start, max, &f, &mut res);
+        }
+        return res;
+    }
+
     pub fn entries(&self) -> Vec<(FKey, FVal, K, V)> {
         let guard = crossbeam_epoch::pin();
         let old_chunk_ref = self.meta.chunk.load(Acquire, &guard);
         let new_chunk_ref = self.meta.new_chunk.load(Acquire, &guard);
         let old_chunk = unsafe { old_chunk_ref.deref() };
-        let new_chunk = unsafe { new_chunk_ref.deref() };
         let mut res = self.all_from_chunk(&*old_chunk);
         if !new_chunk_ref.is_null() && old_chunk_ref != new_chunk_ref {
+            // Only there while migrating
+            let new_chunk = unsafe { new_chunk_ref.deref() };
             res.append(&mut self.all_from_chunk(&*new_chunk));
         }
         return res;
@@ -1433,7 +1493,7 @@ impl<
             unsafe { Shared::<ChunkPtr<K, V, A, ALLOC>>::from_usize(old_chunk_lock) };
         let new_chunk_ins = unsafe { new_chunk_ptr.deref() };
         let old_chunk_ins = unsafe { old_chunk_ptr.deref() };
-        Self::migrate_entries(old_chunk_ins, new_chunk_ins, old_occupation, &guard);
+        Self::migrate_entries(old_chunk_ins, new_chunk_ins, &meta.count, old_occupation, &guard);
         let swap_chunk = meta.chunk.compare_exchange(
             old_chunk_lock,
             new_chunk_ptr.with_tag(0),
@@ -1465,6 +1525,7 @@ impl<
     fn migrate_entries(
         old_chunk_ins: &Chunk<K, V, A, ALLOC>,
         new_chunk_ins: &Chunk<K, V, A, ALLOC>,
+        count: &AtomicUsize,
         old_occupation: usize,
         _guard: &crossbeam_epoch::Guard,
     ) -> usize {
@@ -1515,6 +1576,7 @@ impl<
                             fvalue,
                             old_chunk_ins,
                             new_chunk_ins,
+                            count,
                             old_address,
                             &mut effective_copy,
                         ) {
@@ -1585,6 +1647,7 @@ impl<
         fvalue: FastValue,
         old_chunk_ins: &Chunk<K, V, A, ALLOC>,
         new_chunk_ins: &Chunk<K, V, A, ALLOC>,
+        table_count: &AtomicUsize,
         old_address: usize,
         effective_copy: &mut usize,
     ) -> bool {
@@ -1632,6 +1695,8 @@ impl<
                     old_chunk_ins
                         .attachment
                         .manually_drop(fvalue.act_val::<V>());
+                    // The writer counted its insertion as new, it cannot see the primed old one
+                    table_count.fetch_sub(1, Relaxed);
                     break;
                 }
             } else if k == EMPTY_KEY {
@@ -1922,8 +1987,8 @@ impl<K, V, A: Attachment<K, V>, ALLOC: GlobalAlloc + Default, H: Hasher + Defaul
                 chunk: Default::default(),
                 new_chunk: Default::default(),
                 epoch: AtomicUsize::new(0),
+                count: AtomicUsize::new(0),
             }),
-            count: AtomicUsize::new(0),
             init_cap: self.init_cap,
             attachment_init_meta: self.attachment_init_meta.clone(),
             mark: PhantomData,
@@ -1963,7 +2028,7 @@ impl<K, V, A: Attachment<K, V>, ALLOC: GlobalAlloc + Default, H: Hasher + Defaul
                 new_table.meta.new_chunk.store(Shared::null(), Release);
             }
         }
-        new_table.count.store(self.count.load(Acquire), Release);
+        new_table.meta.count.store(self.meta.count.load(Acquire), Release);
         new_table
     }
 }
@@ -1973,12 +2038,15 @@ impl<K, V, A: Attachment<K, V>, ALLOC: GlobalAlloc + Default, H: Hasher + Defaul
 {
     fn drop(&mut self) {
         let guard = crossbeam_epoch::pin();
+        // The migration thread still works on the chunks and reclaims the old one, let it finish
+        let backoff = crossbeam_utils::Backoff::new();
+        while self.meta.epoch.load(Acquire) & 1 == 1
+            || !self.meta.new_chunk.load(Acquire, &guard).is_null()
+        {
+            backoff.snooze();
+        }
         unsafe {
             guard.defer_destroy(self.meta.chunk.load(Acquire, &guard));
-            let new_chunk_ptr = self.meta.new_chunk.load(Acquire, &guard);
-            if !new_chunk_ptr.is_null() {
-                guard.defer_destroy(new_chunk_ptr);
-            }
         }
     }
 }
@@ -19,17 +19,28 @@ impl<K: Clone + Hash + Eq, V: Clone, A: GlobalAlloc + Default> HashKVAttachment<
     const VAL_SIZE: usize = mem::size_of::<V>();
     const VAL_OFFSET: usize = Self::value_offset();
     const PAIR_SIZE: usize = Self::pair_size();
+    const PAIR_ALIGN: usize = Self::pair_align();
 
     const fn value_offset() -> usize {
-        let padding = align_padding(Self::KEY_SIZE, 2);
+        let padding = align_padding(Self::KEY_SIZE, mem::align_of::<V>());
         Self::KEY_SIZE + padding
     }
 
     const fn pair_size() -> usize {
         let raw_size = Self::value_offset() + Self::VAL_SIZE;
-        let pair_padding = align_padding(raw_size, 2);
+        let pair_padding = align_padding(raw_size, Self::pair_align());
         raw_size + pair_padding
     }
+
+    // Every key and value in the heap stays aligned as long as pairs are
+    const fn pair_align() -> usize {
+        let (key_align, val_align) = (mem::align_of::<K>(), mem::align_of::<V>());
+        if key_align > val_align {
+            key_align
+        } else {
+            val_align
+        }
+    }
 }
 
 impl<K: Clone + Hash + Eq, V: Clone, A: GlobalAlloc + Default> Attachment<K, V>
@@ -39,12 +50,13 @@ impl<K: Clone + Hash + Eq, V: Clone, A: GlobalAlloc + Default> Attachment<K, V>
     type InitMeta = ();
 
     fn heap_size_of(cap: usize) -> usize {
-        cap * Self::PAIR_SIZE
+        // Room to align the first pair, the heap may start at any word
+        cap * Self::PAIR_SIZE + Self::PAIR_ALIGN
     }
 
     fn new(heap_ptr: usize, _meta: &()) -> Self {
         Self {
-            obj_chunk: heap_ptr,
+            obj_chunk: heap_ptr + align_padding(heap_ptr, Self::PAIR_ALIGN),
             shadow: PhantomData,
         }
     }
@@ -89,6 +101,12 @@ impl<K: Clone + Hash + Eq, V: Clone> AttachmentItem<K, V> for HashKVAttachmentIt
         unsafe { (*(val_addr as *mut V)).clone() }
     }
 
+    #[inline(always)]
+    fn with_value<R, F: FnOnce(&V) -> R>(self, f: F) -> R {
+        let val_addr = self.addr + Self::VAL_OFFSET;
+        unsafe { f(&*(val_addr as *const V)) }
+    }
+
     #[inline(always)]
     fn set_value(self, value: V, _old_fval: FVal) {
         let addr = self.addr;
@@ -688,6 +706,47 @@ mod test {
         map.get(&1).unwrap().validate(num_threads);
     }
 
+    #[test]
+    fn sample() {
+        use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+        struct Counted(usize);
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Relaxed);
+                Counted(self.0)
+            }
+        }
+        let map = super::LockingHashMap::<usize, Counted>::with_capacity(64);
+        for i in 5..37 {
+            map.insert(i, Counted(i * 2));
+        }
+        let clones = CLONES.load(Relaxed);
+        for start in [0, 17, usize::MAX] {
+            let samples = map.table.sample(start, 5, |v| v.0);
+            assert_eq!(samples.len(), 5);
+            for (_, k, v) in samples {
+                assert_eq!(v, k * 2);
+            }
+        }
+        // Bounded by what is there, values are never cloned
+        assert_eq!(map.table.sample(3, 100, |v| v.0).len(), 32);
+        assert_eq!(CLONES.load(Relaxed), clones);
+    }
+
+    #[test]
+    fn aligned_pairs() {
+        let map = super::LockingHashMap::<u8, u128>::with_capacity(16);
+        for i in 0..12u8 {
+            map.insert(i, i as u128);
+        }
+        let misaligned = map.table.sample(0, 12, |v| {
+            v as *const u128 as usize % mem::align_of::<u128>()
+        });
+        assert_eq!(misaligned.len(), 12);
+        assert!(misaligned.iter().all(|(_, _, m)| *m == 0));
+    }
+
     #[test]
     fn parallel_hashmap_hybrid() {
         let _ = env_logger::try_init();
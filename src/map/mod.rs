@@ -99,6 +99,13 @@ pub trait Attachment<K, V> {
 pub trait AttachmentItem<K, V> {
     fn get_key(self) -> K;
     fn get_value(self) -> V;
+    // Look at the value without taking a copy, where the attachment keeps it in place
+    fn with_value<R, F: FnOnce(&V) -> R>(self, f: F) -> R
+    where
+        Self: Sized,
+    {
+        f(&self.get_value())
+    }
     fn set_key(self, key: K);
     fn set_value(self, value: V, old_fval: FVal);
     fn erase(self, old_fval: FVal);
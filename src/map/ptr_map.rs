@@ -402,7 +402,8 @@ impl<'a, K: Clone + Hash + Eq, V: Clone, ALLOC: GlobalAlloc + Default, H: Hasher
                     break;
                 }
                 SwapResult::Failed | SwapResult::Aborted => {
-                    backoff.spin();
+                    // May be held as long as a value is loaded, see LinkedHashMap::reserve
+                    backoff.snooze();
                     continue;
                 }
                 SwapResult::NotFound => {
@@ -531,6 +532,48 @@ mod ptr_map {
         }
     }
 
+    #[test]
+    fn revive_tombstone() {
+        let map = PtrHashMap::<usize, usize, System>::with_capacity(16);
+        for i in 0..8 {
+            assert_eq!(map.try_insert(i, i), None);
+        }
+        for i in 0..8 {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        assert_eq!(map.len(), 0);
+        // Inserting over the removed keys takes their tombstones, as new entries
+        for i in 0..8 {
+            assert_eq!(map.try_insert(i, i * 2), None);
+        }
+        assert_eq!(map.len(), 8);
+        for i in 0..8 {
+            assert_eq!(map.insert(i, i * 3), Some(i * 2));
+        }
+        assert_eq!(map.len(), 8);
+    }
+
+    #[test]
+    fn count_over_resize() {
+        // Keys updated while being moved to the new chunk are counted once
+        let map = Arc::new(PtrHashMap::<usize, usize, System>::with_capacity(16));
+        let num = 4096;
+        let threads = (0..4)
+            .map(|t| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for i in 0..num {
+                        map.insert(i, t);
+                        map.insert(i / 2, t);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+        assert_eq!(map.len(), num);
+        assert_eq!(map.entries().len(), num);
+    }
+
     #[test]
     fn parallel_no_resize() {
         let _ = env_logger::try_init();
@@ -1,9 +1,30 @@
+// Lists always take the push, ring buffers are sized for the tests and shall not be full
+#[cfg(test)]
+pub(crate) trait Pushed {
+    fn pushed(self);
+}
+
+#[cfg(test)]
+impl<R, T> Pushed for Result<R, T> {
+    fn pushed(self) {
+        assert!(self.is_ok(), "should have room for the push");
+    }
+}
+
+#[cfg(test)]
+impl<T, const N: usize, S: crate::ring_buffer::Slots<T>> Pushed
+    for crate::ring_buffer::ItemHandle<T, N, S>
+{
+    fn pushed(self) {}
+}
+
 #[macro_export]
 macro_rules! par_list_tests {
     (
         $list_init: block,
         $num: expr
     ) => {
+        use $crate::par_list_test_macros::Pushed;
         use itertools::Itertools;
         use std::{collections::HashSet, sync::Arc, thread};
         #[test]
@@ -18,7 +39,7 @@ macro_rules! par_list_tests {
                     let deque = deque.clone();
                     thread::spawn(move || {
                         for i in nums {
-                            deque.push_front(i);
+                            Pushed::pushed(deque.push_front(i));
                         }
                     })
                 })
@@ -48,7 +69,7 @@ macro_rules! par_list_tests {
                     let deque = deque.clone();
                     thread::spawn(move || {
                         for i in nums {
-                            deque.push_front(i);
+                            Pushed::pushed(deque.push_front(i));
                         }
                     })
                 })
@@ -78,7 +99,7 @@ macro_rules! par_list_tests {
                     let deque = deque.clone();
                     thread::spawn(move || {
                         for i in nums {
-                            deque.push_back(i);
+                            Pushed::pushed(deque.push_back(i));
                         }
                     })
                 })
@@ -108,7 +129,7 @@ macro_rules! par_list_tests {
                     let deque = deque.clone();
                     thread::spawn(move || {
                         for i in nums {
-                            deque.push_back(i);
+                            Pushed::pushed(deque.push_back(i));
                         }
                     })
                 })
@@ -132,7 +153,7 @@ macro_rules! par_list_tests {
             let num: usize = $num;
             let deque = Arc::new($list_init);
             for i in 0..num {
-                deque.push_front(i);
+                Pushed::pushed(deque.push_front(i));
             }
             let ths = (0..num)
                 .chunks(256)
@@ -167,7 +188,7 @@ macro_rules! par_list_tests {
             let num: usize = $num;
             let deque = Arc::new($list_init);
             for i in 0..num {
-                deque.push_front(i);
+                Pushed::pushed(deque.push_front(i));
             }
             let ths = (0..num)
                 .chunks(256)
@@ -195,9 +216,9 @@ macro_rules! par_list_tests {
             for i in 0..num {
                 assert!(all_nums.contains(&i));
             }
-            deque.push_back(1);
-            deque.push_back(2);
-            deque.push_back(3);
+            Pushed::pushed(deque.push_back(1));
+            Pushed::pushed(deque.push_back(2));
+            Pushed::pushed(deque.push_back(3));
             assert_eq!(deque.pop_back().unwrap(), 3);
             assert_eq!(deque.pop_back().unwrap(), 2);
             assert_eq!(deque.pop_back().unwrap(), 1);
@@ -217,9 +238,9 @@ macro_rules! par_list_tests {
                     thread::spawn(move || {
                         nums.into_iter().for_each(|i| {
                             if i % 2 == 0 {
-                                deque.push_front(i);
+                                Pushed::pushed(deque.push_front(i));
                             } else {
-                                deque.push_back(i);
+                                Pushed::pushed(deque.push_back(i));
                             }
                         });
                     })
@@ -251,9 +272,9 @@ macro_rules! par_list_tests {
                     thread::spawn(move || {
                         nums.into_iter().for_each(|i| {
                             if i % 2 == 0 {
-                                deque.push_front(i);
+                                Pushed::pushed(deque.push_front(i));
                             } else {
-                                deque.push_back(i);
+                                Pushed::pushed(deque.push_back(i));
                             }
                         });
                     })
@@ -277,7 +298,7 @@ macro_rules! par_list_tests {
             let num: usize = $num;
             let deque = Arc::new($list_init);
             for i in 0..num {
-                deque.push_front(i);
+                Pushed::pushed(deque.push_front(i));
             }
             let ths = (0..num)
                 .chunks(512)
@@ -319,7 +340,7 @@ macro_rules! par_list_tests {
             let deque = Arc::new($list_init);
             let threshold = (num as f64 * 0.5) as usize;
             for i in 0..threshold {
-                deque.push_front(i);
+                Pushed::pushed(deque.push_front(i));
             }
             let ths = (threshold..num)
                 .chunks(256)
@@ -331,7 +352,7 @@ macro_rules! par_list_tests {
                         nums.into_iter()
                             .map(|i| {
                                 if i % 2 == 0 {
-                                    deque.push_front(i);
+                                    Pushed::pushed(deque.push_front(i));
                                     None
                                 } else {
                                     Some(deque.pop_front().unwrap())
@@ -360,7 +381,7 @@ macro_rules! par_list_tests {
             let deque = Arc::new($list_init);
             let threshold = (num as f64 * 0.5) as usize;
             for i in 0..threshold {
-                deque.push_back(i);
+                Pushed::pushed(deque.push_back(i));
             }
             let ths = (threshold..num)
                 .chunks(256)
@@ -372,7 +393,7 @@ macro_rules! par_list_tests {
                         nums.into_iter()
                             .map(|i| {
                                 if i % 2 == 0 {
-                                    deque.push_back(i);
+                                    Pushed::pushed(deque.push_back(i));
                                     None
                                 } else {
                                     Some(deque.pop_back().unwrap())
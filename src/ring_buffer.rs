@@ -1,3 +1,5 @@
+use std::cmp;
+use std::marker::PhantomData;
 use std::mem::MaybeUninit;
 use std::ptr;
 use std::sync::atomic::Ordering::*;
@@ -10,25 +12,161 @@ use crossbeam_utils::Backoff;
 pub const EMPTY: u8 = 0;
 pub const SENTINEL: u8 = 1;
 pub const ACQUIRED: u8 = 2;
+// Taken by a claim on the item, the slot is left alone until the claim settles whose item it is
+const LOCKED: u8 = 3;
 const EMPTY_SLOT: AtomicU8 = AtomicU8::new(EMPTY);
 
-pub struct RingBuffer<T, const N: usize> {
+// Flags keep the slot state in the low bits and the number of readers pinning the item above.
+// Items are only taken out of slots with no readers
+const STATE_BITS: u8 = 2;
+const STATE_MASK: u8 = (1 << STATE_BITS) - 1;
+const READER: u8 = 1 << STATE_BITS;
+const READER_MASK: u8 = !STATE_MASK;
+
+pub struct RingBuffer<T, const N: usize, S: Slots<T> = InlineSlots<T, N>> {
     pub head: AtomicUsize,
     pub tail: AtomicUsize,
-    pub elements: [MaybeUninit<T>; N],
-    pub flags: [AtomicU8; N],
+    slots: S,
+    // Generation of each slot, moves on every time the slot is filled so handles to earlier items
+    // can tell they are stale. Kept apart from the flags, a word per slot inline would not fit
+    // large buffers on the stack
+    gens: Box<[AtomicUsize]>,
+    // Sequence of each item pushed by push_overwrite, taken once its slot is claimed. Only moves
+    // on with those pushes, unlike the generations that pops, removals and pushes to the front
+    // also move on
+    seqs: Box<[AtomicUsize]>,
+    next_seq: AtomicUsize,
+    _marker: PhantomData<T>,
+}
+
+// Capacity given at runtime, the slots are on the heap
+pub type BoxedRingBuffer<T> = RingBuffer<T, 0, BoxedSlots<T>>;
+
+// Storage of the items and their flags. One slot is always left empty to tell a full buffer from
+// an empty one
+pub trait Slots<T> {
+    fn elements(&self) -> &[MaybeUninit<T>];
+    fn flags(&self) -> &[AtomicU8];
+    fn size(&self) -> usize;
+}
+
+pub struct InlineSlots<T, const N: usize> {
+    elements: [MaybeUninit<T>; N],
+    flags: [AtomicU8; N],
+}
+
+pub struct BoxedSlots<T> {
+    elements: Box<[MaybeUninit<T>]>,
+    flags: Box<[AtomicU8]>,
+}
+
+impl<T, const N: usize> Slots<T> for InlineSlots<T, N> {
+    #[inline(always)]
+    fn elements(&self) -> &[MaybeUninit<T>] {
+        &self.elements
+    }
+
+    #[inline(always)]
+    fn flags(&self) -> &[AtomicU8] {
+        &self.flags
+    }
+
+    #[inline(always)]
+    fn size(&self) -> usize {
+        N
+    }
+}
+
+impl<T> BoxedSlots<T> {
+    pub(crate) fn new(len: usize) -> Self {
+        Self {
+            elements: (0..len).map(|_| MaybeUninit::uninit()).collect(),
+            flags: (0..len).map(|_| AtomicU8::new(EMPTY)).collect(),
+        }
+    }
+}
+
+impl<T> Slots<T> for BoxedSlots<T> {
+    #[inline(always)]
+    fn elements(&self) -> &[MaybeUninit<T>] {
+        &self.elements
+    }
+
+    #[inline(always)]
+    fn flags(&self) -> &[AtomicU8] {
+        &self.flags
+    }
+
+    #[inline(always)]
+    fn size(&self) -> usize {
+        self.elements.len()
+    }
 }
 
-impl<T: Clone, const N: usize> RingBuffer<T, N> {
+impl<T, const N: usize> RingBuffer<T, N> {
     pub fn new() -> Self {
         let elements = unsafe { MaybeUninit::uninit().assume_init() };
         Self {
             head: AtomicUsize::new(0),
             tail: AtomicUsize::new(0),
-            flags: [EMPTY_SLOT; N],
-            elements,
+            slots: InlineSlots {
+                flags: [EMPTY_SLOT; N],
+                elements,
+            },
+            gens: new_words(N),
+            seqs: new_words(N),
+            next_seq: AtomicUsize::new(0),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> BoxedRingBuffer<T> {
+    // Holds up to `cap` items
+    pub fn with_capacity(cap: usize) -> Self {
+        Self {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            slots: BoxedSlots::new(cap + 1),
+            gens: new_words(cap + 1),
+            seqs: new_words(cap + 1),
+            next_seq: AtomicUsize::new(0),
+            _marker: PhantomData,
         }
     }
+}
+
+impl<T, const N: usize, S: Slots<T>> RingBuffer<T, N, S> {
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.slots.size() - 1
+    }
+
+    #[inline(always)]
+    fn flag(&self, idx: usize) -> &AtomicU8 {
+        &self.slots.flags()[idx]
+    }
+
+    #[inline(always)]
+    fn gen(&self, idx: usize) -> &AtomicUsize {
+        &self.gens[idx]
+    }
+
+    // Fill the claimed slot, the generation moves on before the state shows the item. Returns the
+    // new generation
+    #[inline(always)]
+    fn refill(&self, idx: usize, state: u8) -> usize {
+        // The slot is ours once claimed, no one else changes the generation
+        let gen = self.gen(idx).load(Acquire) + 1;
+        self.gen(idx).store(gen, Release);
+        self.flag(idx).store(state, Release);
+        gen
+    }
+
+    #[inline(always)]
+    fn element(&self, idx: usize) -> &MaybeUninit<T> {
+        &self.slots.elements()[idx]
+    }
 
     #[inline(always)]
     pub fn count(&self) -> usize {
@@ -42,42 +180,43 @@ impl<T: Clone, const N: usize> RingBuffer<T, N> {
     }
 
     #[inline(always)]
-    pub fn push_back(&self, data: T) -> Result<ItemRef<T, N>, T> {
-        self.push_general(data, &self.tail, &self.head, Self::incr, false)
+    pub fn push_back(&self, data: T) -> Result<ItemRef<T, N, S>, T> {
+        self.push_general(data, &self.tail, &self.head, Self::incr, false, false)
     }
 
     #[inline(always)]
-    pub fn push_front(&self, data: T) -> Result<ItemRef<T, N>, T> {
-        self.push_general(data, &self.head, &self.tail, Self::decr, true)
+    pub fn push_front(&self, data: T) -> Result<ItemRef<T, N, S>, T> {
+        self.push_general(data, &self.head, &self.tail, Self::decr, true, false)
     }
 
     #[inline(always)]
-    pub unsafe fn push_back_unsafe(&self, data: T) -> Result<ItemRef<T, N>, T> {
+    pub unsafe fn push_back_unsafe(&self, data: T) -> Result<ItemRef<T, N, S>, T> {
         self.push_unsafe_general(data, &self.tail, &self.head, Self::incr, false)
     }
 
     #[inline(always)]
-    pub unsafe fn push_front_unsafe(&self, data: T) -> Result<ItemRef<T, N>, T> {
+    pub unsafe fn push_front_unsafe(&self, data: T) -> Result<ItemRef<T, N, S>, T> {
         self.push_unsafe_general(data, &self.head, &self.tail, Self::decr, true)
     }
 
     #[inline(always)]
-    fn push_general<S>(
+    fn push_general<F>(
         &self,
         data: T,
         target: &AtomicUsize,
         other_side: &AtomicUsize,
-        shift: S,
+        shift: F,
         ahead: bool,
-    ) -> Result<ItemRef<T, N>, T>
+        sequenced: bool,
+    ) -> Result<ItemRef<T, N, S>, T>
     where
-        S: Fn(usize) -> usize,
+        F: Fn(&Self, usize) -> usize,
     {
         let backoff = Backoff::new();
         loop {
             let target_val = target.load(Acquire);
             let other_val = other_side.load(Acquire);
-            let new_target_val = shift(target_val);
+            let new_target_val = shift(self, target_val);
             let pos = if ahead { new_target_val } else { target_val };
             if new_target_val == other_val {
                 // overflow
@@ -86,15 +225,20 @@ impl<T: Clone, const N: usize> RingBuffer<T, N> {
                 .compare_exchange(target_val, new_target_val, AcqRel, Acquire)
                 .is_ok()
             {
-                let flag = &self.flags[pos];
-                let obj = &self.elements[pos];
+                let obj = self.element(pos);
                 unsafe {
                     ptr::write(obj.as_ptr() as *mut T, data);
                 }
-                flag.store(ACQUIRED, Release);
+                if sequenced {
+                    // Shown along with the item by the fill
+                    let seq = self.next_seq.fetch_add(1, AcqRel);
+                    self.seqs[pos].store(seq, Relaxed);
+                }
+                let gen = self.refill(pos, ACQUIRED);
                 return Ok(ItemRef {
                     buffer: self,
                     idx: pos,
+                    gen,
                 });
             }
             backoff.spin();
@@ -122,15 +266,15 @@ impl<T: Clone, const N: usize> RingBuffer<T, N> {
     }
 
     #[inline(always)]
-    fn pop_general<S>(
+    fn pop_general<F>(
         &self,
         target: &AtomicUsize,
         other_side: &AtomicUsize,
-        shift: S,
+        shift: F,
         ahead: bool,
     ) -> Option<T>
     where
-        S: Fn(usize) -> usize,
+        F: Fn(&Self, usize) -> usize,
     {
         let backoff = Backoff::new();
         loop {
@@ -139,12 +283,13 @@ impl<T: Clone, const N: usize> RingBuffer<T, N> {
             if target_val == other_val {
                 return None;
             }
-            let new_target_val = shift(target_val);
+            let new_target_val = shift(self, target_val);
             let pos = if ahead { new_target_val } else { target_val }; // target value is always on step ahead
-            let flag = &self.flags[pos];
-            let obj = &self.elements[pos];
+            let flag = self.flag(pos);
+            let obj = self.element(pos);
             let flag_val = flag.load(Acquire);
-            if flag_val != EMPTY
+            // Slots being filled, read or claimed are waited for
+            if (flag_val == ACQUIRED || flag_val == SENTINEL)
                 && flag
                     .compare_exchange(flag_val, EMPTY, AcqRel, Acquire)
                     .is_ok()
@@ -157,7 +302,7 @@ impl<T: Clone, const N: usize> RingBuffer<T, N> {
                         flag.store(SENTINEL, Release);
                     }
                 };
-                if flag_val != SENTINEL {
+                if state_of(flag_val) != SENTINEL {
                     let res;
                     unsafe {
                         res = obj.assume_init_read();
@@ -173,15 +318,15 @@ impl<T: Clone, const N: usize> RingBuffer<T, N> {
     }
 
     #[inline(always)]
-    fn pop_unsafe_general<S>(
+    fn pop_unsafe_general<F>(
         &self,
         target: &AtomicUsize,
         other_side: &AtomicUsize,
-        shift: S,
+        shift: F,
         ahead: bool,
     ) -> Option<T>
     where
-        S: Fn(usize) -> usize,
+        F: Fn(&Self, usize) -> usize,
     {
         loop {
             let target_val = target.load(Relaxed);
@@ -189,10 +334,10 @@ impl<T: Clone, const N: usize> RingBuffer<T, N> {
             if target_val == other_val {
                 return None;
             }
-            let new_target_val = shift(target_val);
+            let new_target_val = shift(self, target_val);
             let pos = if ahead { new_target_val } else { target_val }; // target value is always on step ahead
-            let flag = &self.flags[pos];
-            let obj = &self.elements[pos];
+            let flag = self.flag(pos);
+            let obj = self.element(pos);
             let flag_val = flag.load(Relaxed);
             debug_assert_ne!(flag_val, EMPTY);
             flag.store(EMPTY, Relaxed);
@@ -210,60 +355,63 @@ impl<T: Clone, const N: usize> RingBuffer<T, N> {
     }
 
     #[inline(always)]
-    fn push_unsafe_general<S>(
+    fn push_unsafe_general<F>(
         &self,
         data: T,
         target: &AtomicUsize,
         other_side: &AtomicUsize,
-        shift: S,
+        shift: F,
         ahead: bool,
-    ) -> Result<ItemRef<T, N>, T>
+    ) -> Result<ItemRef<T, N, S>, T>
     where
-        S: Fn(usize) -> usize,
+        F: Fn(&Self, usize) -> usize,
     {
         let target_val = target.load(Relaxed);
         let other_val = other_side.load(Relaxed);
-        let new_target_val = shift(target_val);
+        let new_target_val = shift(self, target_val);
         let pos = if ahead { new_target_val } else { target_val };
         if new_target_val == other_val {
             // overflow
             return Err(data);
         }
         target.store(new_target_val, Relaxed);
-        let flag = &self.flags[pos];
-        let obj = &self.elements[pos];
+        let flag = self.flag(pos);
+        let obj = self.element(pos);
         unsafe {
             ptr::write(obj.as_ptr() as *mut T, data);
         }
+        let gen = self.gen(pos).load(Relaxed) + 1;
+        self.gen(pos).store(gen, Relaxed);
         flag.store(ACQUIRED, Relaxed);
         return Ok(ItemRef {
             buffer: self,
             idx: pos,
+            gen,
         });
     }
 
     #[inline(always)]
-    pub fn peek_back(&self) -> Option<ItemRef<T, N>> {
+    pub fn peek_back(&self) -> Option<ItemRef<T, N, S>> {
         let tail = self.tail.load(Acquire);
         self.peek_general(tail, &self.head, Self::decr, true)
     }
 
     #[inline(always)]
-    pub fn peek_front(&self) -> Option<ItemRef<T, N>> {
+    pub fn peek_front(&self) -> Option<ItemRef<T, N, S>> {
         let head = self.head.load(Acquire);
         self.peek_general(head, &self.tail, Self::incr, false)
     }
 
     #[inline(always)]
-    fn peek_general<S>(
+    fn peek_general<F>(
         &self,
         mut exp_pos: usize,
         other_side: &AtomicUsize,
-        shift: S,
+        shift: F,
         ahead: bool,
-    ) -> Option<ItemRef<T, N>>
+    ) -> Option<ItemRef<T, N, S>>
     where
-        S: Fn(usize) -> usize,
+        F: Fn(&Self, usize) -> usize,
     {
         let backoff = Backoff::new();
         loop {
@@ -271,23 +419,63 @@ impl<T: Clone, const N: usize> RingBuffer<T, N> {
             if head == exp_pos {
                 return None;
             }
-            let next_pos = shift(exp_pos);
+            let next_pos = shift(self, exp_pos);
             let pos = if ahead { next_pos } else { exp_pos };
-            let flag = &self.flags[pos];
-            let flag_val = flag.load(Acquire);
-            if flag_val != ACQUIRED {
-                exp_pos = next_pos;
-            } else {
-                return Some(ItemRef {
-                    buffer: self,
-                    idx: pos,
-                });
+            let flag = self.flag(pos);
+            match state_of(flag.load(Acquire)) {
+                ACQUIRED => {
+                    return Some(ItemRef {
+                        buffer: self,
+                        idx: pos,
+                        gen: self.gen(pos).load(Acquire),
+                    });
+                }
+                // Claimed, it is either still there or gone once settled
+                LOCKED => {}
+                _ => exp_pos = next_pos,
             }
             backoff.spin();
         }
     }
 
-    pub fn iter_back(&self) -> ItemIter<T, N> {
+    // The item next to the slot at the index, towards the back
+    pub fn next_of(&self, idx: usize) -> Option<ItemRef<T, N, S>> {
+        match self.locate(idx) {
+            cmp::Ordering::Less => self.peek_front(),
+            cmp::Ordering::Equal => {
+                self.peek_general(self.incr(idx), &self.tail, Self::incr, false)
+            }
+            cmp::Ordering::Greater => None,
+        }
+    }
+
+    // The item next to the slot at the index, towards the front
+    pub fn prev_of(&self, idx: usize) -> Option<ItemRef<T, N, S>> {
+        match self.locate(idx) {
+            cmp::Ordering::Less => None,
+            cmp::Ordering::Equal => self.peek_general(idx, &self.head, Self::decr, true),
+            cmp::Ordering::Greater => self.peek_back(),
+        }
+    }
+
+    // Whether the slot is before, within or after the occupied range. Slots left by removals at
+    // the ends are out of the range, they are taken as on the closer side
+    #[inline(always)]
+    fn locate(&self, idx: usize) -> cmp::Ordering {
+        let head = self.head.load(Acquire);
+        let tail = self.tail.load(Acquire);
+        let size = self.slots.size();
+        let offset = (idx + size - head) % size;
+        if offset < (tail + size - head) % size {
+            cmp::Ordering::Equal
+        } else if (head + size - idx) % size <= (idx + size - tail) % size {
+            cmp::Ordering::Less
+        } else {
+            cmp::Ordering::Greater
+        }
+    }
+
+    pub fn iter_back(&self) -> ItemIter<T, N, S> {
         let shift = Self::decr;
         let item = self.peek_back();
         ItemIter {
@@ -300,7 +488,7 @@ impl<T: Clone, const N: usize> RingBuffer<T, N> {
         }
     }
 
-    pub fn iter_front(&self) -> ItemIter<T, N> {
+    pub fn iter_front(&self) -> ItemIter<T, N, S> {
         let shift = Self::incr;
         let item = self.peek_front();
         ItemIter {
@@ -313,6 +501,42 @@ impl<T: Clone, const N: usize> RingBuffer<T, N> {
         }
     }
 
+    // The item the handle refers to, None if the handle is from elsewhere or the item has gone
+    pub fn resolve(&self, handle: &ItemHandle<T, N, S>) -> Option<ItemRef<T, N, S>> {
+        if !ptr::eq(handle.buffer, self) {
+            return None;
+        }
+        let item = ItemRef {
+            buffer: self,
+            idx: handle.idx,
+            gen: handle.gen,
+        };
+        if item.is_live() {
+            Some(item)
+        } else {
+            None
+        }
+    }
+
+    // The item in the slot, whatever its generation is
+    pub(crate) fn item_at(&self, idx: usize) -> ItemRef<T, N, S> {
+        ItemRef {
+            buffer: self,
+            idx,
+            gen: self.gen(idx).load(Acquire),
+        }
+    }
+
+    // Empty out a drained buffer for reuse, generations are kept for the handles still around
+    pub(crate) fn reset(&self) {
+        for flag in self.slots.flags() {
+            debug_assert_ne!(state_of(flag.load(Acquire)), ACQUIRED);
+            flag.store(EMPTY, Release);
+        }
+        self.head.store(0, Release);
+        self.tail.store(0, Release);
+    }
+
     pub fn pop_all(&self) -> Vec<T> {
         let mut res = vec![];
         while let Some(v) = self.pop_front() {
@@ -321,26 +545,176 @@ impl<T: Clone, const N: usize> RingBuffer<T, N> {
         res
     }
 
+    // Push to the back until the items run out or the buffer is full, returns the number pushed.
+    // Slots for as many items as the iterator tells at least are claimed at once, then filled
+    pub fn push_many<I: IntoIterator<Item = T>>(&self, items: I) -> usize {
+        let mut items = items.into_iter();
+        let size = self.slots.size();
+        let backoff = Backoff::new();
+        let mut pushed = 0;
+        loop {
+            if items.size_hint().1 == Some(0) {
+                return pushed;
+            }
+            let tail = self.tail.load(Acquire);
+            let head = self.head.load(Acquire);
+            let free = (head + size - tail - 1) % size;
+            if free == 0 {
+                return pushed;
+            }
+            let num = items.size_hint().0.clamp(1, free);
+            if self
+                .tail
+                .compare_exchange(tail, (tail + num) % size, AcqRel, Acquire)
+                .is_err()
+            {
+                backoff.spin();
+                continue;
+            }
+            let end = (tail + num) % size;
+            // Pushes to the front may have taken the room since the head was read, the claim stops
+            // short of them and gives the rest back
+            let head = self.head.load(Acquire);
+            let num = num.min((head + size - tail - 1) % size);
+            let mut claim = Claim {
+                buffer: self,
+                start: tail,
+                end,
+                num,
+                filled: 0,
+            };
+            while claim.filled < num {
+                let data = match items.next() {
+                    Some(data) => data,
+                    // Fewer items than told, the claim gives back the rest
+                    None => return pushed,
+                };
+                let pos = (tail + claim.filled) % size;
+                unsafe {
+                    ptr::write(self.element(pos).as_ptr() as *mut T, data);
+                }
+                self.refill(pos, ACQUIRED);
+                claim.filled += 1;
+                pushed += 1;
+            }
+        }
+    }
+
+    // Pop from the front into the vector until it is empty or the max is reached, returns the
+    // number popped. Runs of items are taken slot by slot, then the head is moved once
+    pub fn pop_many(&self, out: &mut Vec<T>, max: usize) -> usize {
+        let backoff = Backoff::new();
+        let mut popped = 0;
+        while popped < max {
+            let head = self.head.load(Acquire);
+            let tail = self.tail.load(Acquire);
+            if head == tail {
+                break;
+            }
+            let mut pos = head;
+            let mut claimed = 0;
+            while pos != tail && popped < max {
+                let flag = self.flag(pos);
+                let flag_val = flag.load(Acquire);
+                if (flag_val != ACQUIRED && flag_val != SENTINEL)
+                    || flag
+                        .compare_exchange(flag_val, EMPTY, AcqRel, Acquire)
+                        .is_err()
+                {
+                    break;
+                }
+                if flag_val != SENTINEL {
+                    out.push(unsafe { self.element(pos).assume_init_read() });
+                    popped += 1;
+                }
+                pos = self.incr(pos);
+                claimed += 1;
+            }
+            if claimed == 0 {
+                backoff.spin();
+                continue;
+            }
+            if self
+                .head
+                .compare_exchange(head, pos, AcqRel, Acquire)
+                .is_err()
+            {
+                for pos in (head..head + claimed).map(|pos| pos % self.slots.size()) {
+                    self.flag(pos).store(SENTINEL, Release);
+                }
+            }
+        }
+        popped
+    }
+
+    // Push to the back, evicting from the front to make room when full, returns the evicted items
+    // from the oldest. Others may take the room first under contention, then more than one is
+    // evicted
+    pub fn push_overwrite(&self, mut data: T) -> Vec<T> {
+        if self.capacity() == 0 {
+            return vec![data];
+        }
+        let mut evicted = vec![];
+        loop {
+            match self.push_general(data, &self.tail, &self.head, Self::incr, false, true) {
+                Ok(_) => return evicted,
+                Err(d) => {
+                    data = d;
+                    evicted.extend(self.pop_front());
+                }
+            }
+        }
+    }
+
     #[inline(always)]
-    fn incr(num: usize) -> usize {
-        (num + 1) % N
+    fn incr(&self, num: usize) -> usize {
+        (num + 1) % self.slots.size()
     }
 
     #[inline(always)]
-    fn decr(num: usize) -> usize {
+    fn decr(&self, num: usize) -> usize {
         if num == 0 {
-            N - 1
+            self.slots.size() - 1
         } else {
             num - 1
         }
     }
 }
 
-impl<T, const N: usize> Drop for RingBuffer<T, N> {
+impl<T: Clone, const N: usize, S: Slots<T>> RingBuffer<T, N, S> {
+    // Items from the front to the back, each cloned while pinned in its slot. Items popped or
+    // overwritten before they are reached are left out
+    pub fn snapshot(&self) -> Vec<T> {
+        self.iter_front().filter_map(|item| item.deref()).collect()
+    }
+
+    // Items from the sequence on, see ItemRef::seq, along with the number of those overwritten or
+    // taken out before they were read. Readers keep reading from the sequence plus both numbers
+    pub fn snapshot_since(&self, seq: usize) -> (usize, Vec<T>) {
+        let mut next = seq;
+        let mut missed = 0;
+        let mut items = vec![];
+        for item in self.iter_front() {
+            let item_seq = item.seq();
+            if item_seq < next {
+                // Older than what is read, left by a lap running past the iterator
+                continue;
+            }
+            if let Some(data) = item.deref() {
+                missed += item_seq - next;
+                next = item_seq + 1;
+                items.push(data);
+            }
+        }
+        (missed, items)
+    }
+}
+
+impl<T, const N: usize, S: Slots<T>> Drop for RingBuffer<T, N, S> {
     fn drop(&mut self) {
-        for (i, f) in self.flags.iter().enumerate() {
-            if f.load(Relaxed) == ACQUIRED {
-                let ele = &mut self.elements[i];
+        for (i, f) in self.slots.flags().iter().enumerate() {
+            if state_of(f.load(Relaxed)) == ACQUIRED {
+                let ele = &self.slots.elements()[i];
                 unsafe {
                     ele.assume_init_read();
                 }
@@ -349,112 +723,225 @@ impl<T, const N: usize> Drop for RingBuffer<T, N> {
     }
 }
 
-pub struct ItemRef<'a, T: Clone, const N: usize> {
-    pub buffer: &'a RingBuffer<T, N>,
-    pub idx: usize,
+#[inline(always)]
+fn state_of(flag: u8) -> u8 {
+    flag & STATE_MASK
 }
 
-impl<'a, T: Clone + Default, const N: usize> ItemRef<'a, T, N> {
-    pub fn deref(&self) -> Option<T> {
-        let idx = self.idx;
+fn new_words(size: usize) -> Box<[AtomicUsize]> {
+    (0..size).map(|_| AtomicUsize::new(0)).collect()
+}
+
+// Slots claimed at the back by push_many, up to the end, of which the first num are to be filled.
+// Those left unfilled, when the items run out, the iterator panics or pushes to the front took
+// them, are given back if no one has claimed after them. Otherwise the fillable ones are left for
+// pops to skip, the rest belong to the front
+struct Claim<'a, T, const N: usize, S: Slots<T>> {
+    buffer: &'a RingBuffer<T, N, S>,
+    start: usize,
+    end: usize,
+    num: usize,
+    filled: usize,
+}
+
+impl<'a, T, const N: usize, S: Slots<T>> Drop for Claim<'a, T, N, S> {
+    fn drop(&mut self) {
         let buffer = self.buffer;
-        let flag = &buffer.flags[idx];
-        let ele = &buffer.elements[idx];
-        let obj = unsafe { (&*ele.as_ptr()).clone() };
-        let flag_val = flag.load(Acquire);
-        if flag_val == ACQUIRED {
-            return Some(obj);
-        } else {
-            return None;
+        let size = buffer.slots.size();
+        let filled_end = (self.start + self.filled) % size;
+        if filled_end == self.end {
+            return;
+        }
+        if buffer
+            .tail
+            .compare_exchange(self.end, filled_end, AcqRel, Acquire)
+            .is_err()
+        {
+            // Filled with nothing, the generation moves on as with any other fill
+            for pos in (self.start + self.filled..self.start + self.num).map(|pos| pos % size) {
+                buffer.refill(pos, SENTINEL);
+            }
         }
     }
+}
 
-    pub fn remove(&self) -> Option<T> {
-        let idx = self.idx;
-        let buffer = self.buffer;
-        let flag = &buffer.flags[idx];
-        let ele = &buffer.elements[idx];
-        let obj = unsafe { (&*ele.as_ptr()).clone() };
-        let flag_val = flag.load(Acquire);
-        if flag_val == ACQUIRED
-            && flag
-                .compare_exchange(flag_val, SENTINEL, AcqRel, Acquire)
-                .is_ok()
-        {
-            let head = buffer.head.load(Acquire);
-            let tail = buffer.tail.load(Acquire);
-            if RingBuffer::<T, N>::decr(tail) == idx {
-                let new_tail = RingBuffer::<T, N>::decr(tail);
-                if buffer
-                    .tail
-                    .compare_exchange(tail, new_tail, AcqRel, Acquire)
-                    .is_ok()
-                {
-                    let _succ = flag.compare_exchange(SENTINEL, EMPTY, AcqRel, Acquire);
+pub struct ItemRef<'a, T, const N: usize, S: Slots<T> = InlineSlots<T, N>> {
+    pub buffer: &'a RingBuffer<T, N, S>,
+    pub idx: usize,
+    // Generation of the slot when the item was reached, operations fail once it has moved on
+    pub(crate) gen: usize,
+}
+
+impl<'a, T: Clone, const N: usize, S: Slots<T>> ItemRef<'a, T, N, S> {
+    pub fn deref(&self) -> Option<T> {
+        self.deref_with(T::clone)
+    }
+}
+
+impl<'a, T, const N: usize, S: Slots<T>> ItemRef<'a, T, N, S> {
+    // Read through the item in place. The item is pinned meanwhile, pops and removals wait for
+    // the read to finish before taking it out
+    pub fn deref_with<R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        let _pin = self.pin()?;
+        let ele = self.buffer.element(self.idx);
+        Some(f(unsafe { &*ele.as_ptr() }))
+    }
+
+    // Position of the item among all pushed by push_overwrite, from 0. Only meaningful for those
+    // items and while the item is live, the slot takes the sequence of whatever fills it next
+    pub fn seq(&self) -> usize {
+        self.buffer.seqs[self.idx].load(Acquire)
+    }
+
+    // Whether the item is still in its slot, it may go any moment after
+    pub fn is_live(&self) -> bool {
+        state_of(self.buffer.flag(self.idx).load(Acquire)) == ACQUIRED
+            && self.buffer.gen(self.idx).load(Acquire) == self.gen
+    }
+
+    fn pin(&self) -> Option<ReadPin> {
+        let flag = self.buffer.flag(self.idx);
+        let gen = self.buffer.gen(self.idx);
+        let backoff = Backoff::new();
+        loop {
+            if gen.load(Acquire) != self.gen {
+                return None;
+            }
+            let flag_val = flag.load(Acquire);
+            match state_of(flag_val) {
+                ACQUIRED if flag_val & READER_MASK != READER_MASK => {
+                    if flag
+                        .compare_exchange(flag_val, flag_val + READER, AcqRel, Acquire)
+                        .is_ok()
+                    {
+                        // Refilled before the pin is the item of someone else, let go of it
+                        let pin = ReadPin(flag);
+                        return if gen.load(Acquire) == self.gen {
+                            Some(pin)
+                        } else {
+                            None
+                        };
+                    }
                 }
+                // Full of readers or claimed
+                ACQUIRED | LOCKED => {}
+                _ => return None,
             }
-            if head == idx {
-                let new_head = RingBuffer::<T, N>::incr(head);
-                if buffer
-                    .head
-                    .compare_exchange(head, new_head, AcqRel, Acquire)
+            backoff.spin();
+        }
+    }
+
+    // Swap the flag of the live item for the one given, once no one is reading it. The slot is
+    // locked to check the generation, nothing empties or refills it meanwhile
+    fn claim(&self, new_flag: u8) -> bool {
+        let flag = self.buffer.flag(self.idx);
+        let gen = self.buffer.gen(self.idx);
+        let backoff = Backoff::new();
+        loop {
+            if gen.load(Acquire) != self.gen {
+                return false;
+            }
+            let flag_val = flag.load(Acquire);
+            match state_of(flag_val) {
+                ACQUIRED | LOCKED => {}
+                _ => return false,
+            }
+            if flag_val == ACQUIRED
+                && flag
+                    .compare_exchange(ACQUIRED, LOCKED, AcqRel, Acquire)
                     .is_ok()
-                {
-                    let _succ = flag.compare_exchange(SENTINEL, EMPTY, AcqRel, Acquire);
+            {
+                if gen.load(Acquire) != self.gen {
+                    flag.store(ACQUIRED, Release);
+                    return false;
                 }
+                flag.store(new_flag, Release);
+                return true;
             }
-            unsafe {
-                ele.assume_init_read();
-            }
-            return Some(obj);
-        } else {
+            backoff.spin();
+        }
+    }
+
+    pub fn remove(&self) -> Option<T> {
+        let idx = self.idx;
+        let buffer = self.buffer;
+        let flag = buffer.flag(idx);
+        let ele = buffer.element(idx);
+        if !self.claim(SENTINEL) {
             return None;
         }
+        // The slot is ours, move the item out before it can be reused
+        let obj = unsafe { ele.assume_init_read() };
+        // Leaving the range at an end, the slot is emptied first as the slots out of it are. Pops
+        // wait on it meanwhile, and it is a sentinel again if the end has moved on
+        let retreat = |end: &AtomicUsize, from: usize, to: usize| {
+            flag.store(EMPTY, Release);
+            if end.compare_exchange(from, to, AcqRel, Acquire).is_ok() {
+                return true;
+            }
+            // Not over a push that has taken the slot since
+            let _ = flag.compare_exchange(EMPTY, SENTINEL, AcqRel, Acquire);
+            false
+        };
+        let head = buffer.head.load(Acquire);
+        let tail = buffer.tail.load(Acquire);
+        let retreated = buffer.decr(tail) == idx && retreat(&buffer.tail, tail, idx);
+        // The only item, the tail is already at the head
+        if head == idx && !retreated {
+            retreat(&buffer.head, head, buffer.incr(head));
+        }
+        Some(obj)
     }
 
+    // The item stays live while replaced, readers and removals wait for it as for a reader
     pub fn set(&self, value: T) -> Result<T, ()> {
-        let idx = self.idx;
-        let flag = &self.buffer.flags[idx];
-        let ele = &self.buffer.elements[idx];
-        if flag
-            .compare_exchange(ACQUIRED, SENTINEL, AcqRel, Acquire)
-            .is_err()
-        {
+        let flag = self.buffer.flag(self.idx);
+        let ele = self.buffer.element(self.idx);
+        if !self.claim(ACQUIRED | READER_MASK) {
             return Err(());
-        } else {
-            unsafe {
-                let old = mem::replace(&mut *(ele.as_ptr() as *mut T), value);
-                if flag
-                    .compare_exchange(SENTINEL, ACQUIRED, AcqRel, Acquire)
-                    .is_err()
-                {
-                    return Err(());
-                } else {
-                    return Ok(old);
-                }
-            }
         }
+        let old = unsafe { mem::replace(&mut *(ele.as_ptr() as *mut T), value) };
+        flag.store(ACQUIRED, Release);
+        Ok(old)
     }
 
-    pub fn to_ptr(&self) -> ItemPtr<T, N> {
+    pub fn to_ptr(&self) -> ItemPtr<T, N, S> {
         ItemPtr {
             buffer: &*self.buffer,
             idx: self.idx,
         }
     }
+
+    pub fn handle(&self) -> ItemHandle<T, N, S> {
+        ItemHandle {
+            owner: 0,
+            buffer: self.buffer,
+            idx: self.idx,
+            gen: self.gen,
+        }
+    }
 }
 
-pub struct ItemIter<'a, T: Clone, const N: usize> {
-    buffer: &'a RingBuffer<T, N>,
+// A reader on the item in the slot, let go when dropped even if the read panics
+struct ReadPin<'a>(&'a AtomicU8);
+
+impl<'a> Drop for ReadPin<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(READER, Release);
+    }
+}
+
+pub struct ItemIter<'a, T, const N: usize, S: Slots<T> = InlineSlots<T, N>> {
+    buffer: &'a RingBuffer<T, N, S>,
     other_side: &'a AtomicUsize,
-    shift: fn(usize) -> usize,
+    shift: fn(&RingBuffer<T, N, S>, usize) -> usize,
     idx: usize,
     ahead: bool,
-    current: Option<ItemRef<'a, T, N>>,
+    current: Option<ItemRef<'a, T, N, S>>,
 }
 
-impl<'a, T: Clone + Default, const N: usize> Iterator for ItemIter<'a, T, N> {
-    type Item = ItemRef<'a, T, N>;
+impl<'a, T, const N: usize, S: Slots<T>> Iterator for ItemIter<'a, T, N, S> {
+    type Item = ItemRef<'a, T, N, S>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.current.is_none() {
@@ -462,7 +949,7 @@ impl<'a, T: Clone + Default, const N: usize> Iterator for ItemIter<'a, T, N> {
         }
         let shift = self.shift;
         let curr_pos = if !self.ahead {
-            shift(self.idx)
+            shift(self.buffer, self.idx)
         } else {
             self.idx
         };
@@ -475,25 +962,39 @@ impl<'a, T: Clone + Default, const N: usize> Iterator for ItemIter<'a, T, N> {
     }
 }
 
-#[derive(Clone)]
-pub struct ItemPtr<T: Clone, const N: usize> {
-    buffer: *const RingBuffer<T, N>,
-    idx: usize,
+pub struct ItemPtr<T, const N: usize, S: Slots<T> = InlineSlots<T, N>> {
+    pub(crate) buffer: *const RingBuffer<T, N, S>,
+    pub(crate) idx: usize,
+}
+
+// Not derived, that would require the items to be clone
+impl<T, const N: usize, S: Slots<T>> Clone for ItemPtr<T, N, S> {
+    fn clone(&self) -> Self {
+        Self {
+            buffer: self.buffer,
+            idx: self.idx,
+        }
+    }
 }
 
-impl<T: Clone + Default, const N: usize> ItemPtr<T, N> {
+impl<T, const N: usize, S: Slots<T>> PartialEq for ItemPtr<T, N, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.buffer == other.buffer && self.idx == other.idx
+    }
+}
+
+impl<T, const N: usize, S: Slots<T>> ItemPtr<T, N, S> {
     pub unsafe fn deref(&self) -> &T {
         let buffer = &*self.buffer;
-        &*buffer.elements[self.idx].as_ptr()
+        &*buffer.element(self.idx).as_ptr()
     }
 
-    pub unsafe fn to_ref(&self) -> ItemRef<T, N> {
-        ItemRef {
-            buffer: &*self.buffer,
-            idx: self.idx,
-        }
+    // Takes whatever item is in the slot now
+    pub unsafe fn to_ref(&self) -> ItemRef<T, N, S> {
+        (*self.buffer).item_at(self.idx)
     }
 
+    // Emptied list nodes stay linked, LinkedRingBufferList::remove_ptr reclaims them
     pub unsafe fn remove(&self) -> Option<T> {
         self.to_ref().remove()
     }
@@ -503,7 +1004,63 @@ impl<T: Clone + Default, const N: usize> ItemPtr<T, N> {
     }
 }
 
-unsafe impl<T: Clone, const N: usize> Sync for RingBuffer<T, N> {}
+// A detached reference to an item that is safe to keep around. It is never dereferenced on its
+// own, the buffer or list it came from resolves it and checks the generation of the slot, so a
+// handle to an item that has gone resolves to None even if the slot has been filled again
+pub struct ItemHandle<T, const N: usize, S: Slots<T> = InlineSlots<T, N>> {
+    // Id of the list the handle came from, 0 for a bare buffer
+    pub(crate) owner: usize,
+    pub(crate) buffer: *const RingBuffer<T, N, S>,
+    pub(crate) idx: usize,
+    pub(crate) gen: usize,
+}
+
+impl<T, const N: usize, S: Slots<T>> Clone for ItemHandle<T, N, S> {
+    fn clone(&self) -> Self {
+        Self {
+            owner: self.owner,
+            buffer: self.buffer,
+            idx: self.idx,
+            gen: self.gen,
+        }
+    }
+}
+
+impl<T, const N: usize, S: Slots<T>> PartialEq for ItemHandle<T, N, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.owner == other.owner
+            && self.buffer == other.buffer
+            && self.idx == other.idx
+            && self.gen == other.gen
+    }
+}
+
+impl<T, const N: usize, S: Slots<T>> ItemHandle<T, N, S> {
+    // Resolves to None in any buffer or list, holds the place of an item yet to come
+    pub(crate) fn placeholder() -> Self {
+        Self {
+            owner: 0,
+            buffer: ptr::null(),
+            idx: 0,
+            gen: 0,
+        }
+    }
+
+    // Unchecked fast path, the caller shall make sure the item stays for as long as the pointer is
+    // used
+    pub unsafe fn to_ptr(&self) -> ItemPtr<T, N, S> {
+        ItemPtr {
+            buffer: self.buffer,
+            idx: self.idx,
+        }
+    }
+}
+
+unsafe impl<T: Send, const N: usize, S: Slots<T>> Send for ItemHandle<T, N, S> {}
+unsafe impl<T: Send, const N: usize, S: Slots<T>> Sync for ItemHandle<T, N, S> {}
+
+// Items are moved in and out by any thread, and read in place through refs
+unsafe impl<T: Send + Sync, const N: usize, S: Slots<T>> Sync for RingBuffer<T, N, S> {}
 
 #[cfg(test)]
 mod test {
@@ -626,8 +1183,306 @@ mod test {
         assert_eq!(*ring.pop_front().unwrap(), 42);
     }
 
+    #[test]
+    pub fn stale_handles() {
+        let ring = RingBuffer::<usize, 4>::new();
+        let handle = ring.push_back(1).unwrap().handle();
+        assert_eq!(ring.resolve(&handle).unwrap().deref(), Some(1));
+        assert_eq!(ring.pop_front(), Some(1));
+        assert!(ring.resolve(&handle).is_none());
+        // Fill the slot again, the handle still refers to the item that has gone
+        for i in 2..5 {
+            ring.push_back(i).unwrap();
+        }
+        assert_eq!(ring.pop_front(), Some(2));
+        let reused = ring.push_back(5).unwrap();
+        assert_eq!(reused.idx, handle.idx);
+        assert!(ring.resolve(&handle).is_none());
+        let handle = reused.handle();
+        assert_eq!(ring.resolve(&handle).unwrap().set(6), Ok(5));
+        assert_eq!(ring.resolve(&handle).unwrap().remove(), Some(6));
+        assert!(ring.resolve(&handle).is_none());
+        let other = RingBuffer::<usize, 4>::new();
+        let handle = ring.push_back(7).unwrap().handle();
+        assert!(other.resolve(&handle).is_none());
+    }
+
+    #[test]
+    pub fn pinned_reads() {
+        let ring = Arc::new(RingBuffer::<String, 4>::new());
+        let handle = ring.push_back("item".to_string()).unwrap().handle();
+        let item = ring.resolve(&handle).unwrap();
+        let popper = item
+            .deref_with(|s| {
+                let ring = ring.clone();
+                let popper = thread::spawn(move || ring.pop_front());
+                thread::sleep(std::time::Duration::from_millis(50));
+                // The pop waits for the read to finish
+                assert!(!popper.is_finished());
+                assert_eq!(s, "item");
+                popper
+            })
+            .unwrap();
+        assert_eq!(popper.join().unwrap(), Some("item".to_string()));
+        assert_eq!(item.deref(), None);
+        assert_eq!(item.remove(), None);
+    }
+
+    #[test]
+    pub fn boxed() {
+        // Far beyond what fits on the stack inline
+        let cap = 1 << 22;
+        let ring = RingBuffer::with_capacity(cap);
+        assert_eq!(ring.capacity(), cap);
+        for i in 0..cap {
+            assert!(ring.push_back(i).is_ok(), "on {}", i);
+        }
+        assert_eq!(ring.push_front(cap).err(), Some(cap));
+        assert_eq!(ring.peek_back().unwrap().deref(), Some(cap - 1));
+        assert_eq!(
+            ring.iter_front().take(3).filter_map(|r| r.deref()).count(),
+            3
+        );
+        let handle = ring.peek_front().unwrap().handle();
+        assert_eq!(ring.pop_front(), Some(0));
+        assert!(ring.resolve(&handle).is_none());
+        for i in 1..cap {
+            assert_eq!(ring.pop_front(), Some(i));
+        }
+        assert_eq!(ring.pop_back(), None);
+    }
+
+    #[test]
+    pub fn batch() {
+        let ring = RingBuffer::<usize, 8>::new();
+        let mut items = 0..10;
+        assert_eq!(ring.push_many(&mut items), 7);
+        assert_eq!(items.next(), Some(7));
+        let mut out = vec![];
+        assert_eq!(ring.pop_many(&mut out, 3), 3);
+        assert_eq!(out, vec![0, 1, 2]);
+        // Wraps around, one by one as the lower bound tells nothing
+        assert_eq!(ring.push_many((10..20).filter(|i| i % 2 == 0)), 3);
+        assert_eq!(ring.pop_front(), Some(3));
+        assert_eq!(ring.pop_many(&mut out, 100), 6);
+        assert_eq!(out, vec![0, 1, 2, 4, 5, 6, 10, 12, 14]);
+        assert_eq!(ring.pop_many(&mut out, 100), 0);
+        assert_eq!(ring.push_many(vec![]), 0);
+        assert!(ring.peek_front().is_none());
+    }
+
+    #[test]
+    pub fn overwrite() {
+        let ring = RingBuffer::<usize, 4>::new();
+        for i in 0..3 {
+            assert_eq!(ring.push_overwrite(i), vec![]);
+        }
+        assert_eq!(ring.snapshot_since(0), (0, vec![0, 1, 2]));
+        let handle = ring.peek_front().unwrap().handle();
+        assert_eq!(ring.push_overwrite(3), vec![0]);
+        assert!(ring.resolve(&handle).is_none());
+        assert_eq!(ring.snapshot(), vec![1, 2, 3]);
+        for i in 4..10 {
+            assert_eq!(ring.push_overwrite(i), vec![i - 3]);
+            assert_eq!(ring.peek_back().unwrap().seq(), i);
+        }
+        // Read up to 2 before, lapped by the 4 items after
+        assert_eq!(ring.snapshot_since(3), (4, vec![7, 8, 9]));
+        assert_eq!(ring.snapshot_since(9), (0, vec![9]));
+        assert_eq!(ring.snapshot_since(10), (0, vec![]));
+        assert_eq!(ring.pop_front(), Some(7));
+        assert_eq!(ring.push_overwrite(10), vec![]);
+        assert_eq!(ring.snapshot(), vec![8, 9, 10]);
+        let empty = RingBuffer::<usize, 1>::new();
+        assert_eq!(empty.push_overwrite(1), vec![1]);
+    }
+
+    #[test]
+    pub fn overwrite_after_removals() {
+        // Sequences only move on with the pushes, not with the slots taken out and refilled
+        let ring = RingBuffer::<usize, 4>::new();
+        for i in 0..3 {
+            assert_eq!(ring.push_overwrite(i), vec![]);
+        }
+        assert_eq!(ring.pop_back(), Some(2));
+        assert_eq!(ring.push_overwrite(3), vec![]);
+        assert_eq!(ring.peek_back().unwrap().seq(), 3);
+        assert_eq!(ring.snapshot_since(0), (1, vec![0, 1, 3]));
+        assert_eq!(ring.iter_front().nth(1).unwrap().remove(), Some(1));
+        assert_eq!(ring.snapshot_since(1), (2, vec![3]));
+        assert_eq!(ring.snapshot_since(3), (0, vec![3]));
+        assert_eq!(ring.push_overwrite(4), vec![0]);
+        assert_eq!(ring.peek_back().unwrap().seq(), 4);
+        assert_eq!(ring.pop_back(), Some(4));
+        assert_eq!(ring.push_overwrite(5), vec![]);
+        assert_eq!(ring.peek_back().unwrap().seq(), 5);
+        assert_eq!(ring.snapshot_since(4), (1, vec![5]));
+        assert_eq!(ring.snapshot(), vec![3, 5]);
+    }
+
+    #[test]
+    pub fn par_overwrite() {
+        // Every item comes out once, evicted, popped or left
+        let ring = Arc::new(RingBuffer::<usize, 64>::new());
+        let num = 10240;
+        let done = Arc::new(AtomicBool::new(false));
+        let pushers = (0..4)
+            .map(|t| {
+                let ring = ring.clone();
+                thread::spawn(move || {
+                    (t * num..(t + 1) * num)
+                        .flat_map(|i| ring.push_overwrite(i))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let popper = {
+            let ring = ring.clone();
+            thread::spawn(move || {
+                let mut popped = vec![];
+                for _ in 0..num {
+                    popped.extend(ring.pop_front());
+                }
+                popped
+            })
+        };
+        // Reading along never sees an item twice
+        let reader = {
+            let ring = ring.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                let mut seq = 0;
+                let mut read = vec![];
+                while !done.load(Acquire) {
+                    let (missed, items) = ring.snapshot_since(seq);
+                    seq += missed + items.len();
+                    read.extend(items);
+                }
+                read
+            })
+        };
+        let mut all = pushers
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect::<Vec<_>>();
+        done.store(true, Release);
+        all.extend(popper.join().unwrap());
+        all.extend(ring.pop_all());
+        all.sort();
+        assert_eq!(all, (0..4 * num).collect::<Vec<_>>());
+        let mut read = reader.join().unwrap();
+        let len = read.len();
+        read.sort();
+        read.dedup();
+        assert_eq!(read.len(), len);
+    }
+
+    #[test]
+    pub fn batch_unknown_length() {
+        let ring = RingBuffer::<usize, 8>::new();
+        let mut items = 0..2;
+        // No upper bound told
+        assert_eq!(ring.push_many(std::iter::from_fn(|| items.next())), 2);
+        for i in 2..7 {
+            assert!(ring.push_back(i).is_ok());
+        }
+        assert!(ring.push_back(7).is_err());
+        assert_eq!(ring.pop_all(), (0..7).collect::<Vec<_>>());
+        // Slots claimed for the items after the panic are given back
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            ring.push_many((0..5).map(|i| if i == 2 { panic!("no item") } else { i }))
+        }));
+        assert!(res.is_err());
+        assert_eq!(ring.pop_front(), Some(0));
+        assert_eq!(ring.pop_front(), Some(1));
+        assert_eq!(ring.pop_front(), None);
+        assert_eq!(ring.push_many(0..7), 7);
+    }
+
+    #[test]
+    pub fn par_batch() {
+        let ring = Arc::new(RingBuffer::<usize, 128>::new());
+        let num = 10240;
+        let popped = Arc::new(AtomicUsize::new(0));
+        let producers = (0..4)
+            .map(|t| {
+                let ring = ring.clone();
+                thread::spawn(move || {
+                    let mut items = (t * num..(t + 1) * num).peekable();
+                    while items.peek().is_some() {
+                        if ring.push_many(items.by_ref().take(16)) == 0 {
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let consumers = (0..4)
+            .map(|_| {
+                let ring = ring.clone();
+                let popped = popped.clone();
+                thread::spawn(move || {
+                    let mut out = vec![];
+                    while popped.load(Acquire) < 4 * num {
+                        match ring.pop_many(&mut out, 16) {
+                            0 => thread::yield_now(),
+                            n => {
+                                popped.fetch_add(n, AcqRel);
+                            }
+                        }
+                    }
+                    out
+                })
+            })
+            .collect::<Vec<_>>();
+        producers.into_iter().for_each(|t| t.join().unwrap());
+        let mut all = consumers
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect::<Vec<_>>();
+        all.sort();
+        assert_eq!(all, (0..4 * num).collect::<Vec<_>>());
+    }
+
+    #[test]
+    pub fn par_batch_and_push_front() {
+        for _ in 0..1024 {
+            let ring = Arc::new(RingBuffer::<usize, 16>::new());
+            let front = {
+                let ring = ring.clone();
+                thread::spawn(move || {
+                    (0..10)
+                        .take_while(|i| ring.push_front(100 + i).is_ok())
+                        .count()
+                })
+            };
+            let mut items = 0..10;
+            let mut batched = 0;
+            loop {
+                match ring.push_many(items.by_ref().take(4)) {
+                    0 => break,
+                    n => batched += n,
+                }
+            }
+            let fronted = front.join().unwrap();
+            let all = ring.pop_all();
+            // Batches never run over the items pushed to the front. Both ends taking the last
+            // free slot at once is left as with single pushes, the buffer then reads empty
+            if !all.is_empty() {
+                let expected = (100..100 + fronted).rev().chain(0..batched);
+                assert_eq!(all, expected.collect::<Vec<_>>());
+            }
+        }
+    }
+
     const NUM: usize = 20480;
     const CAP: usize = 20480 * 2;
 
     par_list_tests!({ RingBuffer::<_, CAP>::new() }, NUM);
+
+    mod boxed_par {
+        use super::*;
+
+        par_list_tests!({ BoxedRingBuffer::with_capacity(CAP) }, NUM);
+    }
 }
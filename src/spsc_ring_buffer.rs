@@ -0,0 +1,266 @@
+// A ring buffer for exactly one producer and one consumer, the halves are split apart so the types
+// keep it that way. Each side owns its index and only stores it, reading the other one only when
+// the copy cached from last time tells the buffer is full or empty. One slot is left empty to tell
+// a full buffer from an empty one, as in RingBuffer
+use crossbeam_utils::CachePadded;
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::{
+        atomic::{AtomicUsize, Ordering::*},
+        Arc,
+    },
+};
+
+pub struct SpscRingBuffer<T> {
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+    // The side owning a slot by the indices is the only one touching it
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+pub struct Producer<T> {
+    buffer: Arc<SpscRingBuffer<T>>,
+    tail: usize,
+    head_cache: usize,
+}
+
+pub struct Consumer<T> {
+    buffer: Arc<SpscRingBuffer<T>>,
+    head: usize,
+    tail_cache: usize,
+}
+
+impl<T> SpscRingBuffer<T> {
+    // Holds up to `cap` items
+    pub fn with_capacity(cap: usize) -> Self {
+        Self {
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            slots: (0..cap + 1)
+                .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+                .collect(),
+        }
+    }
+
+    pub fn split(self) -> (Producer<T>, Consumer<T>) {
+        let buffer = Arc::new(self);
+        (
+            Producer {
+                buffer: buffer.clone(),
+                tail: 0,
+                head_cache: 0,
+            },
+            Consumer {
+                buffer,
+                head: 0,
+                tail_cache: 0,
+            },
+        )
+    }
+
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    #[inline(always)]
+    fn incr(&self, num: usize) -> usize {
+        (num + 1) % self.slots.len()
+    }
+
+    #[inline(always)]
+    unsafe fn write(&self, idx: usize, data: T) {
+        (*self.slots[idx].get()).write(data);
+    }
+
+    #[inline(always)]
+    unsafe fn read(&self, idx: usize) -> T {
+        (*self.slots[idx].get()).assume_init_read()
+    }
+}
+
+impl<T> Producer<T> {
+    pub fn push(&mut self, data: T) -> Result<(), T> {
+        let next = self.buffer.incr(self.tail);
+        if next == self.head_cache {
+            self.head_cache = self.buffer.head.load(Acquire);
+            if next == self.head_cache {
+                return Err(data);
+            }
+        }
+        unsafe {
+            self.buffer.write(self.tail, data);
+        }
+        self.tail = next;
+        self.buffer.tail.store(next, Release);
+        Ok(())
+    }
+
+    // Push until the items run out or the buffer is full, returns the number pushed. Items are
+    // published to the consumer at once
+    pub fn push_many<I: IntoIterator<Item = T>>(&mut self, items: I) -> usize {
+        let mut items = items.into_iter();
+        let mut pushed = 0;
+        let mut tail = self.tail;
+        loop {
+            let next = self.buffer.incr(tail);
+            if next == self.head_cache {
+                self.head_cache = self.buffer.head.load(Acquire);
+                if next == self.head_cache {
+                    break;
+                }
+            }
+            match items.next() {
+                Some(data) => unsafe { self.buffer.write(tail, data) },
+                None => break,
+            }
+            tail = next;
+            pushed += 1;
+        }
+        if pushed > 0 {
+            self.tail = tail;
+            self.buffer.tail.store(tail, Release);
+        }
+        pushed
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        if !self.ready() {
+            return None;
+        }
+        let data = unsafe { self.buffer.read(self.head) };
+        self.head = self.buffer.incr(self.head);
+        self.buffer.head.store(self.head, Release);
+        Some(data)
+    }
+
+    // The front item stays put until popped, the producer cannot reach its slot
+    pub fn peek(&mut self) -> Option<&T> {
+        if !self.ready() {
+            return None;
+        }
+        Some(unsafe { (*self.buffer.slots[self.head].get()).assume_init_ref() })
+    }
+
+    // Pop into the vector until the buffer is empty or the max is reached, returns the number
+    // popped. Slots are handed back to the producer at once
+    pub fn pop_many(&mut self, out: &mut Vec<T>, max: usize) -> usize {
+        let mut popped = 0;
+        while popped < max && self.ready() {
+            out.push(unsafe { self.buffer.read(self.head) });
+            self.head = self.buffer.incr(self.head);
+            popped += 1;
+        }
+        if popped > 0 {
+            self.buffer.head.store(self.head, Release);
+        }
+        popped
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    // Whether there is an item at the head, reloading the tail only when it seems empty
+    #[inline(always)]
+    fn ready(&mut self) -> bool {
+        if self.head == self.tail_cache {
+            self.tail_cache = self.buffer.tail.load(Acquire);
+        }
+        self.head != self.tail_cache
+    }
+}
+
+impl<T> Drop for SpscRingBuffer<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        while head != tail {
+            unsafe {
+                self.read(head);
+            }
+            head = self.incr(head);
+        }
+    }
+}
+
+unsafe impl<T: Send> Sync for SpscRingBuffer<T> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    pub fn general() {
+        let (mut producer, mut consumer) = SpscRingBuffer::with_capacity(3).split();
+        assert_eq!(producer.capacity(), 3);
+        assert_eq!(consumer.pop(), None);
+        for lap in 0..4 {
+            for i in 0..3 {
+                assert_eq!(producer.push(lap * 3 + i), Ok(()));
+            }
+            assert_eq!(producer.push(42), Err(42));
+            assert_eq!(consumer.peek(), Some(&(lap * 3)));
+            for i in 0..3 {
+                assert_eq!(consumer.pop(), Some(lap * 3 + i));
+            }
+            assert_eq!(consumer.peek(), None);
+        }
+        // Left items are dropped with the buffer
+        let item = Arc::new(1);
+        let (mut producer, consumer) = SpscRingBuffer::with_capacity(2).split();
+        producer.push(item.clone()).unwrap();
+        drop(consumer);
+        drop(producer);
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+    #[test]
+    pub fn batch() {
+        let (mut producer, mut consumer) = SpscRingBuffer::with_capacity(7).split();
+        let mut items = 0..10;
+        assert_eq!(producer.push_many(&mut items), 7);
+        assert_eq!(items.next(), Some(7));
+        let mut out = vec![];
+        assert_eq!(consumer.pop_many(&mut out, 3), 3);
+        assert_eq!(producer.push_many(10..20), 3);
+        assert_eq!(consumer.pop_many(&mut out, 100), 7);
+        assert_eq!(out, vec![0, 1, 2, 3, 4, 5, 6, 10, 11, 12]);
+        assert_eq!(consumer.pop_many(&mut out, 100), 0);
+        assert_eq!(producer.push_many(None), 0);
+    }
+
+    #[test]
+    pub fn across_threads() {
+        let num = 409600;
+        let (mut producer, mut consumer) = SpscRingBuffer::with_capacity(128).split();
+        let pusher = thread::spawn(move || {
+            let mut items = (0..num).peekable();
+            while items.peek().is_some() {
+                if producer.push_many(items.by_ref().take(32)) == 0 {
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < num {
+            match consumer.pop() {
+                Some(v) => {
+                    assert_eq!(v, expected);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        pusher.join().unwrap();
+        assert_eq!(consumer.pop(), None);
+    }
+}
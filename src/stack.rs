@@ -13,7 +13,7 @@ pub struct RingBufferNode<T, const N: usize> {
     pub next: AtomicArc<Self>,
 }
 
-impl<T: Clone + Default, const B: usize> LinkedRingBufferStack<T, B> {
+impl<T, const B: usize> LinkedRingBufferStack<T, B> {
     pub fn new() -> Self {
         Self {
             head: AtomicArc::null(),
@@ -91,8 +91,8 @@ impl<T: Clone + Default, const B: usize> LinkedRingBufferStack<T, B> {
     }
 }
 
-unsafe impl<T, const B: usize> Sync for LinkedRingBufferStack<T, B> {}
-unsafe impl<T, const B: usize> Send for LinkedRingBufferStack<T, B> {}
+unsafe impl<T: Send, const B: usize> Sync for LinkedRingBufferStack<T, B> {}
+unsafe impl<T: Send, const B: usize> Send for LinkedRingBufferStack<T, B> {}
 
 impl<T, const B: usize> Drop for LinkedRingBufferStack<T, B> {
     fn drop(&mut self) {
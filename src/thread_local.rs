@@ -3,10 +3,9 @@
 use crate::map::{Map, PassthroughHasher};
 use crate::{map::LiteHashMap, stack::LinkedRingBufferStack};
 use std::alloc::System;
-use std::cell::Cell;
 use std::marker::PhantomData;
-use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::*;
+use std::sync::atomic::{AtomicPtr, AtomicUsize};
 use std::{mem, ptr};
 
 static GLOBAL_COUNTER: AtomicUsize = AtomicUsize::new(1);
@@ -23,7 +22,8 @@ struct ThreadMeta {
 const FAST_THREADS: usize = 512;
 
 pub struct ThreadLocal<T> {
-    fast_map: [Cell<*mut T>; FAST_THREADS],
+    // Only set by the owning thread, but read by any thread visiting all the objects
+    fast_map: [AtomicPtr<T>; FAST_THREADS],
     reserve_map: LiteHashMap<usize, *mut T, System, PassthroughHasher>,
     _marker: PhantomData<T>,
 }
@@ -43,11 +43,13 @@ impl<T> ThreadLocal<T> {
         unsafe {
             let tid = ThreadMeta::get_id();
             if tid < FAST_THREADS {
-                let cell = &self.fast_map[tid];
-                if cell.get().is_null() {
-                    cell.set(Box::into_raw(Box::new(new())));
+                let slot = &self.fast_map[tid];
+                let mut addr = slot.load(Relaxed);
+                if addr.is_null() {
+                    addr = Box::into_raw(Box::new(new()));
+                    slot.store(addr, Release);
                 }
-                &mut *(cell.get() as *mut T)
+                &mut *addr
             } else {
                 let obj_ptr = self
                     .reserve_map
@@ -56,6 +58,23 @@ impl<T> ThreadLocal<T> {
             }
         }
     }
+
+    // Visit objects of all threads, including those of threads already exited
+    pub fn for_each<F: FnMut(&T)>(&self, mut f: F) {
+        unsafe {
+            for slot in self.fast_map.iter() {
+                let addr = slot.load(Acquire);
+                if !addr.is_null() {
+                    f(&*addr);
+                }
+            }
+            for (_, v) in self.reserve_map.entries() {
+                if !v.is_null() {
+                    f(&*v);
+                }
+            }
+        }
+    }
 }
 
 impl ThreadMeta {
@@ -89,15 +108,14 @@ impl<T> Drop for ThreadLocal<T> {
                 Box::from_raw(v as *mut T);
             }
         }
-        for cell in self.fast_map.iter() {
-            let addr = cell.get();
+        for slot in self.fast_map.iter_mut() {
+            let addr = mem::replace(slot.get_mut(), ptr::null_mut());
             if addr.is_null() {
                 continue;
             }
             unsafe {
-                Box::from_raw(addr as *mut T);
+                Box::from_raw(addr);
             }
-            cell.set(0 as *mut T);
         }
     }
 }
@@ -0,0 +1,447 @@
+// A concurrent cache with W-TinyLFU admission, in the style of Caffeine
+// Window LRU admits new entries, main SLRU keeps them if they are more frequent than its victims
+use crate::{
+    linked_map::LinkedHashMap,
+    map::{hash_key, Map, PtrHashMap},
+};
+use crossbeam_utils::Backoff;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering::*},
+        Arc,
+    },
+};
+
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_SEEDS: [u64; SKETCH_DEPTH] = [
+    0xc3a5c85c97cb3127,
+    0xb492b66fbe98f273,
+    0x9ae16a3b2f90404f,
+    0xcbf29ce484222325,
+];
+const COUNTERS_PER_WORD: usize = 16;
+const COUNTER_MAX: u64 = 15;
+const RESET_MASK: u64 = 0x7777_7777_7777_7777;
+const SAMPLE_FACTOR: usize = 10;
+
+const WINDOW_PERCENT: usize = 1;
+const PROTECTED_PERCENT: usize = 80;
+
+// Segment tags, set once the entry is in the segment
+const WINDOW: u8 = 0;
+const PROBATION: u8 = 1;
+const PROTECTED: u8 = 2;
+
+// Count-Min sketch with 4-bit counters, halved periodically to age out old frequencies
+pub struct FrequencySketch {
+    table: Vec<AtomicU64>,
+    row_mask: usize,
+    words_per_row: usize,
+    additions: AtomicUsize,
+    sample_size: usize,
+}
+
+pub struct TinyLFUCache<K: Clone + Hash + Eq, V: Clone, const N: usize> {
+    window: LinkedHashMap<K, V, N>,
+    probation: LinkedHashMap<K, V, N>,
+    protected: LinkedHashMap<K, V, N>,
+    // Segment of every cached key. A key is tagged before it first goes into the window and untagged
+    // after it leaves the cache, so a miss never caches a key already on its way between segments
+    segments: PtrHashMap<K, Arc<AtomicU8>>,
+    sketch: FrequencySketch,
+    window_cap: usize,
+    protected_cap: usize,
+    main_cap: usize,
+    // Entries in probation and protected, a slot is taken before moving an entry in
+    main_len: AtomicUsize,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl<K: Clone + Hash + Eq, V: Clone, const N: usize> TinyLFUCache<K, V, N> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 1, "capacity should be larger than 1");
+        let window_cap = (capacity * WINDOW_PERCENT / 100).max(1);
+        let main_cap = capacity - window_cap;
+        let protected_cap = main_cap * PROTECTED_PERCENT / 100;
+        Self {
+            window: LinkedHashMap::with_capacity(window_cap.next_power_of_two()),
+            probation: LinkedHashMap::with_capacity(main_cap.next_power_of_two()),
+            protected: LinkedHashMap::with_capacity(protected_cap.max(1).next_power_of_two()),
+            segments: PtrHashMap::with_capacity(capacity.next_power_of_two()),
+            sketch: FrequencySketch::new(capacity),
+            window_cap,
+            protected_cap,
+            main_cap,
+            main_len: AtomicUsize::new(0),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn get<FF: Fn(&K) -> Option<V> + Send, EF: Fn(K, V) + Send>(
+        &self,
+        key: &K,
+        fetch_fn: FF,
+        evict_fn: EF,
+    ) -> Option<V> {
+        let hash = hash_key::<K, DefaultHasher>(key);
+        self.sketch.increment(hash);
+        if let Some(v) = self.find(key) {
+            self.hits.fetch_add(1, Relaxed);
+            return Some(v);
+        }
+        self.misses.fetch_add(1, Relaxed);
+        let v = fetch_fn(key)?;
+        if self
+            .segments
+            .try_insert(key.clone(), Arc::new(AtomicU8::new(WINDOW)))
+            .is_some()
+        {
+            // Cached by another miss meanwhile
+            return Some(v);
+        }
+        self.window.insert_front(key.clone(), v.clone());
+        while self.window.len() > self.window_cap {
+            match self.window.pop_back() {
+                Some(candidate) => self.admit(candidate.0, candidate.1, &evict_fn),
+                None => break,
+            }
+        }
+        Some(v)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let v = match self.window.remove(key) {
+            Some(v) => v,
+            None => {
+                let v = self
+                    .probation
+                    .remove(key)
+                    .or_else(|| self.protected.remove(key))?;
+                self.main_len.fetch_sub(1, AcqRel);
+                v
+            }
+        };
+        self.segments.remove(key);
+        Some(v)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.window.contains_key(key)
+            || self.probation.contains_key(key)
+            || self.protected.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.window.len() + self.probation.len() + self.protected.len()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Relaxed),
+            misses: self.misses.load(Relaxed),
+        }
+    }
+
+    #[inline(always)]
+    fn find(&self, key: &K) -> Option<V> {
+        let backoff = Backoff::new();
+        loop {
+            if let Some(v) = self.window.get_to_front(key) {
+                return Some(v);
+            }
+            if let Some(v) = self.protected.get_to_front(key) {
+                return Some(v);
+            }
+            let segment = self.segments.get(key)?;
+            // Hit on probation segment shall promote the entry to protected. Only the one moving
+            // the tag does, others look again until the entry is in place
+            if segment
+                .compare_exchange(PROBATION, PROTECTED, AcqRel, Acquire)
+                .is_err()
+            {
+                backoff.spin();
+                continue;
+            }
+            // Popped as a victim meanwhile
+            let v = self.probation.remove(key)?;
+            self.protected.insert_front(key.clone(), v.clone());
+            while self.protected.len() > self.protected_cap {
+                match self.protected.pop_back() {
+                    Some(demoted) => self.move_to_probation(demoted.0, demoted.1, true),
+                    None => break,
+                }
+            }
+            return Some(v);
+        }
+    }
+
+    fn move_to_probation(&self, key: K, value: V, front: bool) {
+        let segment = self.segments.get(&key);
+        if front {
+            self.probation.insert_front(key, value);
+        } else {
+            self.probation.insert_back(key, value);
+        }
+        if let Some(segment) = segment {
+            segment.store(PROBATION, Release);
+        }
+    }
+
+    fn evict<EF: Fn(K, V)>(&self, key: K, value: V, evict_fn: &EF) {
+        self.segments.remove(&key);
+        evict_fn(key, value);
+    }
+
+    // Candidate from the window competes with the victim of the probation segment
+    fn admit<EF: Fn(K, V)>(&self, key: K, value: V, evict_fn: &EF) {
+        let backoff = Backoff::new();
+        let victim = loop {
+            if self
+                .main_len
+                .fetch_update(AcqRel, Acquire, |len| {
+                    if len < self.main_cap {
+                        Some(len + 1)
+                    } else {
+                        None
+                    }
+                })
+                .is_ok()
+            {
+                self.move_to_probation(key, value, true);
+                return;
+            }
+            if let Some(victim) = self
+                .probation
+                .pop_back()
+                .or_else(|| self.protected.pop_back())
+            {
+                break victim;
+            }
+            // Full, but the entries are on their way between segments
+            backoff.snooze();
+        };
+        let candidate_freq = self.sketch.frequency(hash_key::<K, DefaultHasher>(&key));
+        let victim_freq = self
+            .sketch
+            .frequency(hash_key::<K, DefaultHasher>(&victim.0));
+        if candidate_freq > victim_freq {
+            self.move_to_probation(key, value, true);
+            self.evict(victim.0, victim.1, evict_fn);
+        } else {
+            self.move_to_probation(victim.0, victim.1, false);
+            self.evict(key, value, evict_fn);
+        }
+    }
+}
+
+impl FrequencySketch {
+    pub fn new(capacity: usize) -> Self {
+        let counters = capacity.max(COUNTERS_PER_WORD).next_power_of_two();
+        let words_per_row = counters / COUNTERS_PER_WORD;
+        Self {
+            table: (0..words_per_row * SKETCH_DEPTH)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            row_mask: counters - 1,
+            words_per_row,
+            additions: AtomicUsize::new(0),
+            sample_size: capacity.max(1) * SAMPLE_FACTOR,
+        }
+    }
+
+    pub fn frequency(&self, hash: usize) -> u64 {
+        (0..SKETCH_DEPTH)
+            .map(|row| {
+                let (word, shift) = self.position(hash, row);
+                (self.table[word].load(Relaxed) >> shift) & COUNTER_MAX
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn increment(&self, hash: usize) {
+        let mut added = false;
+        for row in 0..SKETCH_DEPTH {
+            let (word, shift) = self.position(hash, row);
+            added |= self.table[word]
+                .fetch_update(Relaxed, Relaxed, |w| {
+                    if (w >> shift) & COUNTER_MAX < COUNTER_MAX {
+                        Some(w + (1 << shift))
+                    } else {
+                        None
+                    }
+                })
+                .is_ok();
+        }
+        if added && self.additions.fetch_add(1, AcqRel) + 1 == self.sample_size {
+            self.reset();
+        }
+    }
+
+    // Halve all of the counters, aging the frequencies
+    fn reset(&self) {
+        for word in &self.table {
+            let _ = word.fetch_update(Relaxed, Relaxed, |w| Some((w >> 1) & RESET_MASK));
+        }
+        self.additions.fetch_sub(self.sample_size / 2, AcqRel);
+    }
+
+    #[inline(always)]
+    fn position(&self, hash: usize, row: usize) -> (usize, usize) {
+        let h = (hash as u64)
+            .wrapping_add(SKETCH_SEEDS[row])
+            .wrapping_mul(SKETCH_SEEDS[row]);
+        let idx = ((h ^ (h >> 32)) as usize) & self.row_mask;
+        let word = row * self.words_per_row + idx / COUNTERS_PER_WORD;
+        let shift = (idx % COUNTERS_PER_WORD) * 4;
+        (word, shift)
+    }
+}
+
+impl CacheStats {
+    pub fn requests(&self) -> usize {
+        self.hits + self.misses
+    }
+
+    pub fn hit_ratio(&self) -> f64 {
+        let requests = self.requests();
+        if requests == 0 {
+            0.0
+        } else {
+            self.hits as f64 / requests as f64
+        }
+    }
+}
+
+unsafe impl<K: Clone + Hash + Eq + Send, V: Clone + Send, const N: usize> Send
+    for TinyLFUCache<K, V, N>
+{
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lru_cache::LRUCache;
+    use std::{sync::Arc, thread};
+
+    const CAP: usize = 16;
+
+    #[test]
+    pub fn sketch() {
+        let sketch = FrequencySketch::new(64);
+        for _ in 0..10 {
+            sketch.increment(42);
+        }
+        sketch.increment(24);
+        assert!(sketch.frequency(42) >= 10);
+        assert!(sketch.frequency(24) >= 1);
+        for _ in 0..100 {
+            sketch.increment(42);
+        }
+        assert_eq!(sketch.frequency(42), COUNTER_MAX);
+        for i in 0..64 * SAMPLE_FACTOR {
+            sketch.increment(i + 1000);
+        }
+        assert!(sketch.frequency(42) < COUNTER_MAX);
+    }
+
+    #[test]
+    pub fn scan_resistance() {
+        let capacity = 128;
+        let lfu = TinyLFUCache::<usize, usize, CAP>::new(capacity);
+        let lru = LRUCache::<usize, usize, CAP>::new(capacity);
+        let hot = 0..50;
+        for _ in 0..10 {
+            for k in hot.clone() {
+                lfu.get(&k, |k| Some(*k), |_, _| {});
+                lru.get(&k, |k| Some(*k), |_, _| {});
+            }
+        }
+        for k in 1000..3000 {
+            lfu.get(&k, |k| Some(*k), |_, _| {});
+            lru.get(&k, |k| Some(*k), |_, _| {});
+            if k % 200 == 0 {
+                for k in hot.clone() {
+                    lfu.get(&k, |k| Some(*k), |_, _| {});
+                    lru.get(&k, |k| Some(*k), |_, _| {});
+                }
+            }
+        }
+        let before = lfu.stats();
+        for k in hot.clone() {
+            assert_eq!(lfu.get(&k, |k| Some(*k), |_, _| {}), Some(k));
+        }
+        let after = lfu.stats();
+        let lru_hits = hot
+            .filter(|k| lru.get(k, |_| None, |_, _| {}).is_some())
+            .count();
+        assert!(after.hits - before.hits >= 45, "{:?} {:?}", before, after);
+        assert!(after.hits - before.hits > lru_hits);
+        assert!(lfu.len() <= capacity);
+    }
+
+    #[test]
+    pub fn concurrent() {
+        let cache = Arc::new(TinyLFUCache::<usize, usize, CAP>::new(128));
+        let threads = (0..4)
+            .map(|t| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for i in 0..512 {
+                        let k = t * 1000 + (i * 7) % 64;
+                        assert_eq!(cache.get(&k, |k| Some(*k * 2), |_, _| {}), Some(k * 2));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(cache.stats().requests(), 4 * 512);
+        // Misses racing for the last slots of the main segments never overshoot
+        assert!(cache.len() <= 128, "{}", cache.len());
+    }
+
+    #[test]
+    pub fn promotion_races_misses() {
+        // Keys promoted while others miss on them end up in one segment, room for all of them
+        let cache = Arc::new(TinyLFUCache::<usize, usize, CAP>::new(256));
+        let threads = (0..4)
+            .map(|t| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for i in 0..4096 {
+                        let k = (i * 7 + t) % 64;
+                        assert_eq!(cache.get(&k, |k| Some(*k), |_, _| {}), Some(k));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+        for k in 0..64 {
+            let copies = [&cache.window, &cache.probation, &cache.protected]
+                .iter()
+                .filter(|segment| segment.contains_key(&k))
+                .count();
+            assert_eq!(copies, 1, "key {}", k);
+        }
+        let listed: usize = [&cache.window, &cache.probation, &cache.protected]
+            .iter()
+            .map(|segment| segment.iter_front_keys().count())
+            .sum();
+        assert_eq!(listed, 64);
+        assert_eq!(cache.len(), 64);
+    }
+}
@@ -1,151 +1,1303 @@
 use std::{
     alloc::{GlobalAlloc, System},
     collections::hash_map::DefaultHasher,
-    hash::Hasher,
-    time::{SystemTime, UNIX_EPOCH},
+    convert::{Infallible, TryFrom},
+    future::Future,
+    hash::{Hash, Hasher},
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering::*},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
+use crate::cache_metrics::{CacheMetrics, MetricsSnapshot, RemovalCause, RemovalListener};
+use crate::codec::{decode_len, encode_len, Codec};
+use crate::flight::{yield_now, Flight, FLIGHTS_CAP};
 use crate::map::base::*;
-use crate::map::obj_map::WordObjectAttachment;
+use crate::map::{FVal, HashKVAttachment, Map, PtrHashMap};
+use crate::rand::XorRand;
 
-type ObjectTable<V, ALLOC, H> = Table<(), V, WordObjectAttachment<V, ALLOC>, ALLOC, H>;
+type EntryTable<K, V, ALLOC, H> =
+    Table<K, TTLEntry<V>, HashKVAttachment<K, TTLEntry<V>, ALLOC>, ALLOC, H>;
+
+// Fast value only keeps a compact stamp of the deadline, full deadline lives in the attachment
+const STAMP_MASK: FVal = !(!0 << 30);
+// Number of entries to compare on each eviction, the oldest of them is evicted
+const EVICTION_SAMPLES: usize = 5;
+// Evict a bit more than required so not every insertion at the bound pays for a scan
+const EVICTION_HEADROOM: usize = 16;
+
+// Durations too long for nanoseconds in u64 saturate, they are as good as forever
+#[inline(always)]
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+pub trait Clock: Send + Sync {
+    // Time elapsed since the origin of the clock
+    fn now(&self) -> Duration;
+}
+
+pub struct MonotonicClock {
+    origin: Instant,
+}
+
+pub struct ManualClock {
+    nanos: AtomicU64,
+}
+
+#[derive(Clone, Copy)]
+pub enum RefreshMode {
+    // Callers wait for the one who is refreshing the expired entry
+    Blocking,
+    // Serve the expired value, for no longer than the stale window, while one caller refreshes
+    StaleWhileRevalidate {
+        stale_window: Duration,
+    },
+    // Refresh once the remaining lifetime drops below the fraction of the lifetime,
+    // serving the current value to others and the expired value within the stale window
+    RefreshAhead {
+        fraction: f64,
+        stale_window: Duration,
+    },
+}
+
+#[derive(Clone, Copy)]
+pub enum NegativeCaching {
+    // Misses live as long as the lifetime given for values
+    Lifetime,
+    // Misses live for the given duration
+    Fixed(Duration),
+    // Misses are never cached, every lookup of a missing key calls the fallback
+    Disabled,
+}
+
+#[derive(Clone)]
+struct TTLEntry<V> {
+    value: Option<V>,
+    born: u64,
+    deadline: u64,
+    refresh_at: u64,
+    // Placeholder of a missing key, claimed by the one loading it
+    loading: bool,
+}
+
+enum Lookup<
+    'a,
+    K: Clone + Hash + Eq,
+    V: Clone,
+    C: Clock,
+    ALLOC: GlobalAlloc + Default,
+    H: Hasher + Default,
+> {
+    Ready(Option<V>),
+    Wait,
+    Load(Claim<'a, K, V, C, ALLOC, H>),
+}
+
+// Claimed load of the key, released on drop unless landed
+struct Claim<
+    'a,
+    K: Clone + Hash + Eq,
+    V: Clone,
+    C: Clock,
+    ALLOC: GlobalAlloc + Default,
+    H: Hasher + Default,
+> {
+    cache: &'a TTLCache<K, V, C, ALLOC, H>,
+    key: &'a K,
+    stamp: FVal,
+    // Entry being refreshed, none for a placeholder
+    old: Option<TTLEntry<V>>,
+    now: u64,
+    flight: Arc<Flight<()>>,
+    landed: bool,
+}
 
 pub struct TTLCache<
+    K: Clone + Hash + Eq,
     V: Clone,
+    C: Clock = MonotonicClock,
     ALLOC: GlobalAlloc + Default = System,
     H: Hasher + Default = DefaultHasher,
 > {
-    table: ObjectTable<V, ALLOC, H>,
+    table: EntryTable<K, V, ALLOC, H>,
+    // Claimed loads, put in once claimed and taken out before the entry is stored or released
+    flights: PtrHashMap<K, Arc<Flight<()>>, ALLOC, H>,
+    clock: C,
+    mode: RefreshMode,
+    negative: NegativeCaching,
+    max_entries: usize,
+    evict_fn: Option<Box<dyn Fn(K, V) + Send + Sync>>,
+    rand: XorRand,
+    metrics: CacheMetrics,
+    listener: Option<RemovalListener<K, V>>,
 }
 
-impl<V: Clone, ALLOC: GlobalAlloc + Default, H: Hasher + Default> TTLCache<V, ALLOC, H> {
+impl<K: Clone + Hash + Eq, V: Clone, ALLOC: GlobalAlloc + Default, H: Hasher + Default>
+    TTLCache<K, V, MonotonicClock, ALLOC, H>
+{
     pub fn with_capacity(cap: usize) -> Self {
+        Self::with_clock(cap, MonotonicClock::new())
+    }
+}
+
+impl<
+        K: Clone + Hash + Eq,
+        V: Clone,
+        C: Clock,
+        ALLOC: GlobalAlloc + Default,
+        H: Hasher + Default,
+    > TTLCache<K, V, C, ALLOC, H>
+{
+    pub fn with_clock(cap: usize, clock: C) -> Self {
         Self {
-            table: ObjectTable::with_capacity(cap, ()),
+            table: EntryTable::with_capacity(cap, ()),
+            flights: PtrHashMap::with_capacity(FLIGHTS_CAP),
+            clock,
+            mode: RefreshMode::Blocking,
+            negative: NegativeCaching::Lifetime,
+            max_entries: usize::MAX,
+            evict_fn: None,
+            rand: XorRand::new(cap.wrapping_mul(2654435761) | 1),
+            metrics: CacheMetrics::new(),
+            listener: None,
+        }
+    }
+
+    pub fn with_refresh_mode(mut self, mode: RefreshMode) -> Self {
+        if let RefreshMode::RefreshAhead { fraction, .. } = mode {
+            assert!(
+                (0.0..=1.0).contains(&fraction),
+                "refresh ahead fraction should be within [0, 1]"
+            );
         }
+        self.mode = mode;
+        self
     }
-    pub fn get<F: Fn(usize) -> Option<V>>(
+
+    pub fn with_negative_caching(mut self, negative: NegativeCaching) -> Self {
+        self.negative = negative;
+        self
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        assert!(
+            max_entries > 0,
+            "cache should be able to hold at least one entry"
+        );
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn with_evict_fn<EF: Fn(K, V) + Send + Sync + 'static>(mut self, evict_fn: EF) -> Self {
+        self.evict_fn = Some(Box::new(evict_fn));
+        self
+    }
+
+    // Called with every value leaving the cache, along with the reason
+    pub fn with_removal_listener<L: Fn(&K, &V, RemovalCause) + Send + Sync + 'static>(
+        mut self,
+        listener: L,
+    ) -> Self {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    pub fn get<F: Fn(&K) -> Option<V>>(
         &self,
-        key: usize,
-        lifetime: usize,
+        key: &K,
+        lifetime: Duration,
         fallback: F,
     ) -> Option<V> {
+        match self.try_get::<Infallible, _>(key, lifetime, |k| Ok(fallback(k))) {
+            Ok(v) => v,
+            Err(e) => match e {},
+        }
+    }
+
+    // Errors from the fallback are returned to the caller and never cached. Callers missing the
+    // same key, or finding it expired, wait for the one loading it. Unless it is a future on the
+    // same thread, blocking would stall it, then they call the fallback by themselves and leave
+    // the entry to the future
+    pub fn try_get<E, F: Fn(&K) -> Result<Option<V>, E>>(
+        &self,
+        key: &K,
+        lifetime: Duration,
+        fallback: F,
+    ) -> Result<Option<V>, E> {
         let backoff = crossbeam_utils::Backoff::new();
-        let guard = crossbeam_epoch::pin();
-        let time = since_the_epoch() as usize;
+        let mut recorded = false;
+        loop {
+            let lookup = self.lookup(key, false, &mut recorded, &crossbeam_epoch::pin());
+            match lookup {
+                Lookup::Ready(value) => return Ok(value),
+                Lookup::Wait => match self.flights.get(key) {
+                    Some(flight) if !flight.can_wait() => return self.load(key, &fallback),
+                    Some(flight) => {
+                        flight.wait();
+                    }
+                    // Claimed a moment ago, or about to be stored
+                    None => backoff.snooze(),
+                },
+                Lookup::Load(claim) => {
+                    // Dropping the claim on error leaves the entry for others to retry
+                    let value = self.load(key, &fallback)?;
+                    claim.land(&value, lifetime);
+                    return Ok(value);
+                }
+            }
+        }
+    }
+
+    // Futures never block on the entry being loaded, they are woken once it is done, or serve the
+    // stale value within the stale window. Loads are claimed the same way as in try_get
+    pub async fn get_async<F: Future<Output = Option<V>>, L: Fn(&K) -> F>(
+        &self,
+        key: &K,
+        lifetime: Duration,
+        loader: L,
+    ) -> Option<V> {
+        let mut recorded = false;
         loop {
-            let (ttl_val, v) = self.table.get(&(), key, true).unwrap_or((0, None));
-            let mem_ttl_time = ttl_val >> 1;
-            let new_ttl = time + lifetime;
-            let new_ttl_val = new_ttl << 1;
-            if mem_ttl_time > time {
-                return v;
+            // Not holding the guard across awaits
+            let lookup = self.lookup(key, true, &mut recorded, &crossbeam_epoch::pin());
+            match lookup {
+                Lookup::Ready(value) => return value,
+                Lookup::Wait => match self.flights.get(key) {
+                    Some(flight) => {
+                        flight.landed().await;
+                    }
+                    // Claimed a moment ago, or about to be stored, there is nothing to wait on yet
+                    None => yield_now().await,
+                },
+                Lookup::Load(claim) => {
+                    let start = Instant::now();
+                    let value = loader(key).await;
+                    self.metrics.record_load(start.elapsed(), true);
+                    claim.land(&value, lifetime);
+                    return value;
+                }
             }
-            if v.is_none() {
+        }
+    }
+
+    // Remove all entries beyond their deadline and stale window, returns number of entries removed
+    pub fn purge_expired(&self) -> usize {
+        let guard = crossbeam_epoch::pin();
+        let now = self.now();
+        self.table
+            .entries()
+            .into_iter()
+            .filter(|(_, stamp, key, entry)| {
+                self.is_expired(entry, now)
+                    && self.evict_entry(key, *stamp, RemovalCause::Expired, &guard)
+            })
+            .count()
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let value = self.table.remove(key, 0)?.1.value?;
+        self.removed(key, &value, RemovalCause::Explicit);
+        Some(value)
+    }
+
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    // Serve the entry, wait for the one loading it, or claim the load through the competition bit
+    // of its stamp. A missing key is claimed by a placeholder entry
+    fn lookup<'a>(
+        &'a self,
+        key: &'a K,
+        led_async: bool,
+        recorded: &mut bool,
+        guard: &crossbeam_epoch::Guard,
+    ) -> Lookup<'a, K, V, C, ALLOC, H> {
+        let now = self.now();
+        let (stamp, entry) = match self.table.get(key, 0, true) {
+            Some((stamp, Some(entry))) => (stamp, entry),
+            _ => {
                 // Not existed
-                return fallback(key).map(|v| {
-                    self.table
-                        .insert(InsertOp::TryInsert, &(), Some(&v), key, new_ttl_val);
-                    v
-                });
+                let placeholder = TTLEntry {
+                    value: None,
+                    born: now,
+                    deadline: 0,
+                    refresh_at: 0,
+                    loading: true,
+                };
+                let stamp = Self::stamp(0);
+                return match self.table.insert(
+                    InsertOp::TryInsert,
+                    key,
+                    Some(&placeholder),
+                    0,
+                    stamp | 1,
+                ) {
+                    None | Some((TOMBSTONE_VALUE, _)) | Some((EMPTY_VALUE, _)) => {
+                        self.record(recorded, false);
+                        Lookup::Load(Claim::new(self, key, stamp, None, now, led_async))
+                    }
+                    _ => Lookup::Wait,
+                };
             }
-            // Expired
-            if ttl_val & 1 == 1 {
-                // First bit indicates there is a competition
-                backoff.spin();
-                continue; // Wait for it to be finished
+        };
+        if entry.loading {
+            self.record(recorded, false);
+            return Lookup::Wait;
+        }
+        if entry.refresh_at > now {
+            self.record(recorded, true);
+            return Lookup::Ready(entry.value);
+        }
+        // Expired, or due to refresh ahead
+        if stamp & 1 == 1 {
+            // First bit indicates there is a competition
+            if entry.deadline.saturating_add(self.stale_window()) > now {
+                // Someone is refreshing, still fine to serve the stale value
+                self.record(recorded, true);
+                return Lookup::Ready(entry.value);
             }
-            match self.table.swap(
-                key,
-                &(),
-                move |fast_value| {
-                    if fast_value == ttl_val {
-                        Some(new_ttl_val)
-                    } else {
-                        None
+            return Lookup::Wait;
+        }
+        if !self.claim(key, stamp, guard) {
+            return Lookup::Wait;
+        }
+        self.record(recorded, entry.deadline > now);
+        Lookup::Load(Claim::new(self, key, stamp, Some(entry), now, led_async))
+    }
+
+    // Only the first outcome of a call counts, waiting for a load takes a few lookups
+    #[inline(always)]
+    fn record(&self, recorded: &mut bool, hit: bool) {
+        if *recorded {
+            return;
+        }
+        *recorded = true;
+        if hit {
+            self.metrics.record_hit();
+        } else {
+            self.metrics.record_miss();
+        }
+    }
+
+    // Sample a few entries from a random slot at a time, the expired ones go and otherwise the
+    // oldest of them, until under the bound. Every insertion over the bound evicts by itself, so
+    // concurrent insertions can never outgrow it
+    fn evict(&self, now: u64) {
+        let guard = crossbeam_epoch::pin();
+        let target = self.max_entries - self.max_entries / EVICTION_HEADROOM;
+        // Samples may all be claimed or gone meanwhile, give up after twice the rounds needed
+        let mut rounds = self.table.len().saturating_sub(target) * 2;
+        while self.table.len() > target && rounds > 0 {
+            rounds -= 1;
+            // Wraps around whatever the table capacity is
+            let start = self.rand.rand();
+            let mut oldest: Option<(FVal, K, u64)> = None;
+            for (stamp, key, (born, expired)) in
+                self.table.sample(start, EVICTION_SAMPLES, |entry| {
+                    (entry.born, self.is_expired(entry, now))
+                })
+            {
+                if expired {
+                    self.evict_entry(&key, stamp, RemovalCause::Expired, &guard);
+                } else if !matches!(&oldest, Some((_, _, b)) if *b <= born) {
+                    oldest = Some((stamp, key, born));
+                }
+            }
+            if let Some((stamp, key, _)) = oldest.filter(|_| self.table.len() > target) {
+                self.evict_entry(&key, stamp, RemovalCause::Size, &guard);
+            }
+        }
+    }
+
+    // Claim the entry as if refreshing it, so entries refreshed in between won't be removed
+    fn evict_entry(
+        &self,
+        key: &K,
+        stamp: FVal,
+        cause: RemovalCause,
+        guard: &crossbeam_epoch::Guard,
+    ) -> bool {
+        if stamp & 1 == 1 {
+            // Being refreshed
+            return false;
+        }
+        if !self.claim(key, stamp, guard) {
+            return false;
+        }
+        match self.table.remove(key, 0) {
+            Some((_, TTLEntry { value, .. })) => {
+                if let Some(value) = value {
+                    self.removed(key, &value, cause);
+                    if let Some(evict_fn) = &self.evict_fn {
+                        evict_fn(key.clone(), value);
                     }
-                },
-                &guard,
-            ) {
-                SwapResult::Succeed(_, _, _) => {}
-                _ => {
-                    backoff.spin();
-                    continue;
                 }
+                true
             }
-            if let Some(comp_v) = fallback(key) {
-                self.table
-                    .insert(InsertOp::Insert, &(), Some(&comp_v), key, new_ttl_val);
-                return Some(comp_v);
-            } else {
-                self.table
-                    .insert(InsertOp::UpsertFast, &(), None, key, new_ttl_val);
-                return None;
+            None => false,
+        }
+    }
+
+    #[inline(always)]
+    fn load<E, F: Fn(&K) -> Result<Option<V>, E>>(
+        &self,
+        key: &K,
+        fallback: &F,
+    ) -> Result<Option<V>, E> {
+        let start = Instant::now();
+        let res = fallback(key);
+        self.metrics.record_load(start.elapsed(), res.is_ok());
+        res
+    }
+
+    #[inline(always)]
+    fn removed(&self, key: &K, value: &V, cause: RemovalCause) {
+        self.metrics.record_removal(cause);
+        if let Some(listener) = &self.listener {
+            listener(key, value, cause);
+        }
+    }
+
+    // Returns false when the value is a miss and misses shall not be cached
+    fn store(
+        &self,
+        op: InsertOp,
+        key: &K,
+        value: &Option<V>,
+        now: u64,
+        lifetime: Duration,
+    ) -> bool {
+        let lifetime = match (value, self.negative) {
+            (Some(_), _) | (None, NegativeCaching::Lifetime) => lifetime,
+            (None, NegativeCaching::Fixed(negative_lifetime)) => negative_lifetime,
+            (None, NegativeCaching::Disabled) => return false,
+        };
+        let entry = self.new_entry(value.clone(), now, lifetime);
+        let stamp = Self::stamp(entry.deadline);
+        self.table.insert(op, key, Some(&entry), 0, stamp);
+        true
+    }
+
+    #[inline(always)]
+    fn claim(&self, key: &K, stamp: FVal, guard: &crossbeam_epoch::Guard) -> bool {
+        self.swap_stamp(key, stamp, stamp | 1, guard)
+    }
+
+    #[inline(always)]
+    fn release(&self, key: &K, stamp: FVal, guard: &crossbeam_epoch::Guard) -> bool {
+        self.swap_stamp(key, stamp | 1, stamp, guard)
+    }
+
+    #[inline(always)]
+    fn swap_stamp(&self, key: &K, from: FVal, to: FVal, guard: &crossbeam_epoch::Guard) -> bool {
+        let swap = move |fast_value: FVal| if fast_value == from { Some(to) } else { None };
+        matches!(
+            self.table.swap(0, key, swap, guard),
+            SwapResult::Succeed(_, _, _)
+        )
+    }
+
+    // Values refreshed ahead of their deadline are replaced, not expired
+    #[inline(always)]
+    fn refresh_cause(expired: bool) -> RemovalCause {
+        if expired {
+            RemovalCause::Expired
+        } else {
+            RemovalCause::Replaced
+        }
+    }
+
+    #[inline(always)]
+    fn is_expired(&self, entry: &TTLEntry<V>, now: u64) -> bool {
+        entry.deadline.saturating_add(self.stale_window()) <= now
+    }
+
+    #[inline(always)]
+    fn new_entry(&self, value: Option<V>, now: u64, lifetime: Duration) -> TTLEntry<V> {
+        let lifetime = nanos(lifetime);
+        let deadline = now.saturating_add(lifetime);
+        let refresh_at = match self.mode {
+            RefreshMode::RefreshAhead { fraction, .. } => {
+                deadline - (lifetime as f64 * fraction) as u64
             }
+            _ => deadline,
+        };
+        TTLEntry {
+            value,
+            born: now,
+            deadline,
+            refresh_at,
+            loading: false,
+        }
+    }
+
+    #[inline(always)]
+    fn stale_window(&self) -> u64 {
+        match self.mode {
+            RefreshMode::Blocking => 0,
+            RefreshMode::StaleWhileRevalidate { stale_window }
+            | RefreshMode::RefreshAhead { stale_window, .. } => nanos(stale_window),
         }
     }
+
+    #[inline(always)]
+    fn now(&self) -> u64 {
+        nanos(self.clock.now())
+    }
+
+    #[inline(always)]
+    fn stamp(deadline: u64) -> FVal {
+        // Lowest bit is reserved for competition, always keep the stamp above the fixed values
+        ((deadline as FVal & STAMP_MASK) | NUM_FIX_V) << 1
+    }
 }
 
-fn since_the_epoch() -> u64 {
-    let start = SystemTime::now();
-    start
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_secs()
+impl<
+        K: Clone + Hash + Eq + Codec,
+        V: Clone + Codec,
+        ALLOC: GlobalAlloc + Default,
+        H: Hasher + Default,
+    > TTLCache<K, V, MonotonicClock, ALLOC, H>
+{
+    pub fn restore<R: Read>(cap: usize, reader: &mut R) -> io::Result<Self> {
+        let cache = Self::with_capacity(cap);
+        cache.restore_from(reader)?;
+        Ok(cache)
+    }
+}
+
+impl<
+        K: Clone + Hash + Eq + Codec,
+        V: Clone + Codec,
+        C: Clock,
+        ALLOC: GlobalAlloc + Default,
+        H: Hasher + Default,
+    > TTLCache<K, V, C, ALLOC, H>
+{
+    // Write entries not yet beyond their deadline, times are relative to now as clocks differ
+    // between processes
+    pub fn dump<W: Write>(&self, writer: &mut W) -> io::Result<usize> {
+        let now = self.now();
+        let entries = self
+            .table
+            .entries()
+            .into_iter()
+            .filter(|(_, _, _, entry)| entry.deadline > now)
+            .collect::<Vec<_>>();
+        encode_len(entries.len(), writer)?;
+        for (_, _, key, entry) in &entries {
+            key.encode(writer)?;
+            entry.value.encode(writer)?;
+            now.saturating_sub(entry.born).encode(writer)?;
+            (entry.deadline - now).encode(writer)?;
+            entry.refresh_at.saturating_sub(now).encode(writer)?;
+        }
+        Ok(entries.len())
+    }
+
+    // Put the dumped entries back with the lifetimes they had left when dumped, returns the number
+    // of entries put back, misses are left out when not cached
+    pub fn restore_from<R: Read>(&self, reader: &mut R) -> io::Result<usize> {
+        let mut restored = 0;
+        for _ in 0..decode_len(reader)? {
+            let key = K::decode(reader)?;
+            let value = Option::<V>::decode(reader)?;
+            let (age, remaining, refresh_in) = (
+                u64::decode(reader)?,
+                u64::decode(reader)?,
+                u64::decode(reader)?,
+            );
+            if value.is_none() && matches!(self.negative, NegativeCaching::Disabled) {
+                continue;
+            }
+            let now = self.now();
+            let entry = TTLEntry {
+                value,
+                born: now.saturating_sub(age),
+                deadline: now.saturating_add(remaining),
+                refresh_at: now.saturating_add(refresh_in),
+                loading: false,
+            };
+            let stamp = Self::stamp(entry.deadline);
+            self.table
+                .insert(InsertOp::Insert, &key, Some(&entry), 0, stamp);
+            restored += 1;
+        }
+        if self.table.len() > self.max_entries {
+            self.evict(self.now());
+        }
+        Ok(restored)
+    }
+}
+
+impl<
+        'a,
+        K: Clone + Hash + Eq,
+        V: Clone,
+        C: Clock,
+        ALLOC: GlobalAlloc + Default,
+        H: Hasher + Default,
+    > Claim<'a, K, V, C, ALLOC, H>
+{
+    fn new(
+        cache: &'a TTLCache<K, V, C, ALLOC, H>,
+        key: &'a K,
+        stamp: FVal,
+        old: Option<TTLEntry<V>>,
+        now: u64,
+        led_async: bool,
+    ) -> Self {
+        let flight = Arc::new(Flight::new(led_async));
+        cache.flights.insert(key.clone(), flight.clone());
+        Self {
+            cache,
+            key,
+            stamp,
+            old,
+            now,
+            flight,
+            landed: false,
+        }
+    }
+
+    // Store the loaded value in place of the claimed entry
+    fn land(mut self, value: &Option<V>, lifetime: Duration) {
+        let (cache, key) = (self.cache, self.key);
+        cache.flights.remove(key);
+        if !cache.store(InsertOp::Insert, key, value, cache.now(), lifetime) {
+            cache.table.remove(key, 0);
+        }
+        match &self.old {
+            Some(TTLEntry {
+                value: Some(old),
+                deadline,
+                ..
+            }) => cache.removed(
+                key,
+                old,
+                TTLCache::<K, V, C, ALLOC, H>::refresh_cause(*deadline <= self.now),
+            ),
+            None if cache.table.len() > cache.max_entries => cache.evict(self.now),
+            _ => {}
+        }
+        self.flight.land(Some(()));
+        self.landed = true;
+    }
+}
+
+impl<
+        'a,
+        K: Clone + Hash + Eq,
+        V: Clone,
+        C: Clock,
+        ALLOC: GlobalAlloc + Default,
+        H: Hasher + Default,
+    > Drop for Claim<'a, K, V, C, ALLOC, H>
+{
+    // The load failed or was abandoned, the next caller claims it again
+    fn drop(&mut self) {
+        if self.landed {
+            return;
+        }
+        self.cache.flights.remove(self.key);
+        if self.old.is_some() {
+            self.cache
+                .release(self.key, self.stamp, &crossbeam_epoch::pin());
+        } else {
+            self.cache.table.remove(self.key, 0);
+        }
+        self.flight.land(None);
+    }
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.nanos.fetch_add(nanos(duration), AcqRel);
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.nanos.store(nanos(now), Release);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Acquire))
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::{alloc::System, collections::hash_map::DefaultHasher, thread, time::Duration};
+    use std::{
+        sync::{Arc, Barrier, Mutex},
+        thread,
+        time::Duration,
+    };
 
-    use super::{since_the_epoch, TTLCache};
+    use super::{ManualClock, NegativeCaching, RefreshMode, RemovalCause, TTLCache};
+    use crate::flight::{flag_waker, run_local, YieldNow};
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::atomic::{AtomicUsize, Ordering::*},
+        task::{Context, Poll},
+    };
 
     #[test]
     fn general() {
-        let cache = TTLCache::<_, System, DefaultHasher>::with_capacity(16);
-        let now = since_the_epoch();
+        let cache = TTLCache::<_, _, _>::with_clock(16, ManualClock::new());
+        let sec = Duration::from_secs(1);
         assert_eq!(
-            cache.get(42, 1, |k| {
-                assert_eq!(k, 42);
-                Some(now)
+            cache.get(&42, sec, |k| {
+                assert_eq!(*k, 42);
+                Some(12)
             }),
-            Some(now)
+            Some(12)
         );
         assert_eq!(
-            cache.get(42, 0, |k| {
-                assert_eq!(k, 42);
+            cache.get(&42, Duration::ZERO, |k| {
+                assert_eq!(*k, 42);
                 Some(24)
             }),
-            Some(now)
+            Some(12)
         );
-        thread::sleep(Duration::from_secs(2));
+        cache.clock().advance(Duration::from_millis(999));
+        assert_eq!(cache.get(&42, Duration::ZERO, |_| Some(36)), Some(12));
+        cache.clock().advance(Duration::from_millis(1));
         assert_eq!(
-            cache.get(42, 0, |k| {
-                assert_eq!(k, 42);
+            cache.get(&42, Duration::ZERO, |k| {
+                assert_eq!(*k, 42);
                 Some(48)
             }),
             Some(48)
         );
         assert_eq!(
-            cache.get(24, 1, |k| {
-                assert_eq!(k, 24);
+            cache.get(&24, sec, |k| {
+                assert_eq!(*k, 24);
                 Some(96)
             }),
             Some(96)
         );
         assert_eq!(
-            cache.get(42, 0, |k| {
-                assert_eq!(k, 42);
+            cache.get(&42, Duration::ZERO, |k| {
+                assert_eq!(*k, 42);
                 Some(48)
             }),
             Some(48)
         );
         assert_eq!(
-            cache.get(24, 1, |k| {
-                assert_eq!(k, 24);
+            cache.get(&24, sec, |k| {
+                assert_eq!(*k, 24);
                 Some(1024)
             }),
             Some(96)
         );
+        // Lifetimes beyond nanoseconds in u64 saturate instead of wrapping around
+        assert_eq!(cache.get(&7, Duration::MAX, |_| Some(7)), Some(7));
+        cache.clock().advance(Duration::from_secs(1 << 20));
+        assert_eq!(cache.get(&7, sec, |_| None), Some(7));
+    }
+
+    #[test]
+    fn generic_keys() {
+        let cache = TTLCache::<String, usize>::with_capacity(16);
+        let min = Duration::from_secs(60);
+        let key = "lightning".to_string();
+        assert_eq!(cache.get(&key, min, |k| Some(k.len())), Some(9));
+        assert_eq!(cache.get(&key, min, |_| Some(0)), Some(9));
+        assert_eq!(cache.get(&"other".to_string(), min, |_| None), None);
+    }
+
+    #[test]
+    fn concurrent_refresh() {
+        let cache = Arc::new(TTLCache::<_, _, _>::with_clock(16, ManualClock::new()));
+        let sec = Duration::from_secs(1);
+        cache.get(&1, sec, |_| Some(0usize));
+        cache.clock().advance(sec);
+        let threads = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                thread::spawn(move || cache.get(&1, sec, |_| Some(1usize)))
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            assert_eq!(t.join().unwrap(), Some(1));
+        }
+    }
+
+    #[test]
+    fn stale_while_revalidate() {
+        let cache = Arc::new(
+            TTLCache::<_, _, _>::with_clock(16, ManualClock::new()).with_refresh_mode(
+                RefreshMode::StaleWhileRevalidate {
+                    stale_window: Duration::from_secs(5),
+                },
+            ),
+        );
+        let sec = Duration::from_secs(1);
+        assert_eq!(cache.get(&1, sec, |_| Some(1usize)), Some(1));
+        cache.clock().advance(sec);
+        let started = Arc::new(Barrier::new(2));
+        let release = Arc::new(Barrier::new(2));
+        let refresher = {
+            let cache = cache.clone();
+            let started = started.clone();
+            let release = release.clone();
+            thread::spawn(move || {
+                cache.get(&1, sec, |_| {
+                    started.wait();
+                    release.wait();
+                    Some(2)
+                })
+            })
+        };
+        started.wait();
+        assert_eq!(
+            cache.get(&1, sec, |_| panic!("should serve stale")),
+            Some(1)
+        );
+        cache.clock().advance(Duration::from_secs(4));
+        assert_eq!(
+            cache.get(&1, sec, |_| panic!("should serve stale")),
+            Some(1)
+        );
+        release.wait();
+        assert_eq!(refresher.join().unwrap(), Some(2));
+        assert_eq!(cache.get(&1, sec, |_| panic!("should be fresh")), Some(2));
+    }
+
+    #[test]
+    fn refresh_ahead() {
+        let cache = TTLCache::<_, _, _>::with_clock(16, ManualClock::new()).with_refresh_mode(
+            RefreshMode::RefreshAhead {
+                fraction: 0.5,
+                stale_window: Duration::ZERO,
+            },
+        );
+        let lifetime = Duration::from_secs(10);
+        assert_eq!(cache.get(&1, lifetime, |_| Some(1usize)), Some(1));
+        cache.clock().advance(Duration::from_secs(4));
+        assert_eq!(cache.get(&1, lifetime, |_| panic!("too early")), Some(1));
+        cache.clock().advance(Duration::from_secs(2));
+        assert_eq!(cache.get(&1, lifetime, |_| Some(2)), Some(2));
+        // Refreshed before its deadline, the old value is replaced rather than expired
+        assert_eq!(cache.metrics().replacements, 1);
+        assert_eq!(cache.metrics().expirations, 0);
+        cache.clock().advance(Duration::from_secs(4));
+        assert_eq!(cache.get(&1, lifetime, |_| panic!("too early")), Some(2));
+    }
+
+    #[test]
+    fn bounded() {
+        let evicted = Arc::new(Mutex::new(vec![]));
+        let cache = {
+            let evicted = evicted.clone();
+            TTLCache::<_, _, _>::with_clock(16, ManualClock::new())
+                .with_max_entries(4)
+                .with_evict_fn(move |k, v| evicted.lock().unwrap().push((k, v)))
+        };
+        let sec = Duration::from_secs(1);
+        cache.get(&0, sec, |_| Some(0));
+        cache.clock().advance(Duration::from_millis(1));
+        for i in 1..4 {
+            cache.get(&i, Duration::from_secs(10), |k| Some(*k));
+            cache.clock().advance(Duration::from_millis(1));
+        }
+        assert_eq!(cache.len(), 4);
+        cache.clock().advance(sec);
+        // Expired entry goes first
+        cache.get(&4, sec, |k| Some(*k));
+        assert_eq!(cache.len(), 4);
+        assert_eq!(*evicted.lock().unwrap(), vec![(0, 0)]);
+        for i in 5..64 {
+            cache.get(&i, sec, |k| Some(*k));
+            assert!(cache.len() <= 4);
+        }
+        assert_eq!(evicted.lock().unwrap().len(), 60);
+    }
+
+    #[test]
+    fn bounded_concurrent() {
+        let evicted = Arc::new(AtomicUsize::new(0));
+        let cache = {
+            let evicted = evicted.clone();
+            Arc::new(
+                TTLCache::<_, _, _>::with_clock(64, ManualClock::new())
+                    .with_max_entries(32)
+                    .with_evict_fn(move |_, _| {
+                        evicted.fetch_add(1, Relaxed);
+                    }),
+            )
+        };
+        let threads = (0..8)
+            .map(|t| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for i in 0..256 {
+                        cache.get(&(t * 256 + i), Duration::from_secs(1), |k| Some(*k));
+                        assert!(cache.len() <= 32 + 8);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert!(cache.len() <= 32);
+        assert_eq!(cache.len() + evicted.load(Relaxed), 8 * 256);
+    }
+
+    #[test]
+    fn purge_expired() {
+        let cache = TTLCache::<_, _, _>::with_clock(16, ManualClock::new());
+        for i in 0..8 {
+            cache.get(&i, Duration::from_secs(i as u64 % 2 + 1), |k| Some(*k));
+        }
+        assert_eq!(cache.purge_expired(), 0);
+        cache.clock().advance(Duration::from_secs(1));
+        assert_eq!(cache.purge_expired(), 4);
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.get(&1, Duration::ZERO, |_| None), Some(1));
+        assert_eq!(cache.get(&2, Duration::ZERO, |_| None), None);
+    }
+
+    #[test]
+    fn negative_caching() {
+        let sec = Duration::from_secs(1);
+        let cache = TTLCache::<_, _, _>::with_clock(16, ManualClock::new())
+            .with_negative_caching(NegativeCaching::Fixed(Duration::from_millis(100)));
+        assert_eq!(cache.get(&1, sec, |_| None), None);
+        assert_eq!(cache.get(&1, sec, |_| Some(1)), None);
+        cache.clock().advance(Duration::from_millis(100));
+        assert_eq!(cache.get(&1, sec, |_| Some(1)), Some(1));
+        cache.clock().advance(sec);
+        assert_eq!(cache.get(&1, sec, |_| None), None);
+        cache.clock().advance(Duration::from_millis(100));
+        assert_eq!(cache.get(&1, sec, |_| Some(2)), Some(2));
+
+        let cache = TTLCache::<_, _, _>::with_clock(16, ManualClock::new())
+            .with_negative_caching(NegativeCaching::Disabled);
+        assert_eq!(cache.get(&1, sec, |_| None), None);
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.get(&1, sec, |_| Some(1)), Some(1));
+        cache.clock().advance(sec);
+        assert_eq!(cache.get(&1, sec, |_| None), None);
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.get(&1, sec, |_| Some(2)), Some(2));
+    }
+
+    #[test]
+    fn fallible_fallback() {
+        let sec = Duration::from_secs(1);
+        let cache = TTLCache::<_, _, _>::with_clock(16, ManualClock::new());
+        assert_eq!(cache.try_get(&1, sec, |_| Err("down")), Err("down"));
+        assert_eq!(cache.len(), 0);
+        assert_eq!(
+            cache.try_get::<(), _>(&1, sec, |_| Ok(Some(1))),
+            Ok(Some(1))
+        );
+        cache.clock().advance(sec);
+        assert_eq!(cache.try_get(&1, sec, |_| Err("down")), Err("down"));
+        // Failed refresh releases the entry for the next caller
+        assert_eq!(
+            cache.try_get::<(), _>(&1, sec, |_| Ok(Some(2))),
+            Ok(Some(2))
+        );
+        assert_eq!(cache.try_get::<(), _>(&1, sec, |_| Ok(None)), Ok(Some(2)));
+    }
+
+    #[test]
+    fn metrics_and_listener() {
+        let removed = Arc::new(Mutex::new(vec![]));
+        let cache = {
+            let removed = removed.clone();
+            TTLCache::<_, _, _>::with_clock(16, ManualClock::new())
+                .with_max_entries(4)
+                .with_removal_listener(move |k, v, cause| {
+                    removed.lock().unwrap().push((*k, *v, cause))
+                })
+        };
+        let sec = Duration::from_secs(1);
+        assert_eq!(cache.get(&1, sec, |k| Some(*k)), Some(1));
+        assert_eq!(cache.get(&1, sec, |_| Some(0)), Some(1));
+        cache.clock().advance(sec);
+        assert_eq!(cache.get(&1, sec, |_| Some(10)), Some(10));
+        assert_eq!(cache.try_get(&2, sec, |_| Err(())), Err(()));
+        assert_eq!(cache.remove(&1), Some(10));
+        for i in 10..15 {
+            cache.get(&i, sec, |k| Some(*k));
+            cache.clock().advance(Duration::from_millis(1));
+        }
+        cache.clock().advance(sec * 2);
+        cache.purge_expired();
+        let metrics = cache.metrics();
+        assert_eq!(metrics.hits, 1);
+        assert_eq!(metrics.misses, 8);
+        assert_eq!(metrics.loads, 7);
+        assert_eq!(metrics.load_failures, 1);
+        assert_eq!(metrics.explicit_removals, 1);
+        assert_eq!(metrics.size_evictions, 1);
+        assert_eq!(metrics.expirations, 1 + 4);
+        let removed = removed.lock().unwrap();
+        assert_eq!(
+            removed[..2],
+            [
+                (1, 1, RemovalCause::Expired),
+                (1, 10, RemovalCause::Explicit)
+            ]
+        );
+        assert_eq!(
+            removed.iter().filter(|r| r.2 == RemovalCause::Size).count(),
+            1
+        );
+        assert_eq!(removed.len(), metrics.removals() as usize);
+    }
+
+    #[test]
+    fn get_async() {
+        let cache = TTLCache::<_, _, _>::with_clock(16, ManualClock::new()).with_refresh_mode(
+            RefreshMode::StaleWhileRevalidate {
+                stale_window: Duration::from_secs(1),
+            },
+        );
+        let sec = Duration::from_secs(1);
+        let loads = AtomicUsize::new(0);
+        let loader = |k: &usize| {
+            let k = *k;
+            let loads = &loads;
+            async move {
+                let n = loads.fetch_add(1, Relaxed);
+                YieldNow(false).await;
+                Some(k * 10 + n)
+            }
+        };
+        let keys = [1, 2];
+        let gets = (0..6)
+            .map(|i| {
+                Box::pin(cache.get_async(&keys[i % 2], sec, loader))
+                    as Pin<Box<dyn Future<Output = _>>>
+            })
+            .collect();
+        assert_eq!(run_local(gets), [Some(10usize), Some(21)].repeat(3));
+        assert_eq!(loads.load(Relaxed), 2);
+        // Expired but within the stale window, the first one refreshes and others get stale value
+        cache.clock().advance(sec);
+        let gets = (0..3)
+            .map(|_| {
+                Box::pin(cache.get_async(&keys[0], sec, loader)) as Pin<Box<dyn Future<Output = _>>>
+            })
+            .collect();
+        assert_eq!(run_local(gets), vec![Some(12usize), Some(10), Some(10)]);
+        assert_eq!(cache.get(&1, sec, |_| None), Some(12));
+        assert_eq!(cache.metrics().loads, 3);
+    }
+
+    #[test]
+    fn mixed_sync_async() {
+        let cache = Arc::new(TTLCache::<usize, usize, _>::with_clock(
+            16,
+            ManualClock::new(),
+        ));
+        let sec = Duration::from_secs(1);
+        let loads = Arc::new(AtomicUsize::new(0));
+        let loader = |k: &usize| {
+            let k = *k;
+            let loads = loads.clone();
+            async move {
+                loads.fetch_add(1, Relaxed);
+                Some(k * 10)
+            }
+        };
+        // Loaded by a thread, futures wait for it
+        let claimed = Arc::new(Barrier::new(2));
+        let leader = {
+            let cache = cache.clone();
+            let claimed = claimed.clone();
+            let loads = loads.clone();
+            thread::spawn(move || {
+                cache.get(&1, sec, |k| {
+                    claimed.wait();
+                    thread::sleep(Duration::from_millis(50));
+                    loads.fetch_add(1, Relaxed);
+                    Some(k * 10)
+                })
+            })
+        };
+        claimed.wait();
+        let gets = (0..4)
+            .map(|_| Box::pin(cache.get_async(&1, sec, loader)) as Pin<Box<dyn Future<Output = _>>>)
+            .collect();
+        assert_eq!(run_local(gets), vec![Some(10usize); 4]);
+        assert_eq!(leader.join().unwrap(), Some(10));
+        assert_eq!(loads.load(Relaxed), 1);
+        // Loaded by a future, threads wait for it
+        let claimed = Arc::new(Barrier::new(5));
+        let waiters = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                let claimed = claimed.clone();
+                thread::spawn(move || {
+                    claimed.wait();
+                    cache.try_get::<(), _>(&2, sec, |_| panic!("should wait for the future"))
+                })
+            })
+            .collect::<Vec<_>>();
+        let leader = cache.get_async(&2, sec, |k: &usize| {
+            let k = *k;
+            let claimed = claimed.clone();
+            async move {
+                claimed.wait();
+                thread::sleep(Duration::from_millis(50));
+                YieldNow(false).await;
+                Some(k * 10)
+            }
+        });
+        assert_eq!(run_local(vec![Box::pin(leader)]), vec![Some(20)]);
+        for t in waiters {
+            assert_eq!(t.join().unwrap(), Ok(Some(20)));
+        }
+        // One load for each key, everyone else waited for it
+        assert_eq!(cache.metrics().loads, 2);
+        assert_eq!(cache.metrics().misses, 10);
+    }
+
+    #[test]
+    fn async_waiters_are_woken() {
+        let cache = Arc::new(TTLCache::<usize, usize, _>::with_clock(
+            16,
+            ManualClock::new(),
+        ));
+        let sec = Duration::from_secs(1);
+        let started = Arc::new(Barrier::new(2));
+        let release = Arc::new(Barrier::new(2));
+        let leader = {
+            let (cache, started, release) = (cache.clone(), started.clone(), release.clone());
+            thread::spawn(move || {
+                cache.get(&1, sec, |k| {
+                    started.wait();
+                    release.wait();
+                    Some(k * 10)
+                })
+            })
+        };
+        started.wait();
+        let (woken, waker) = flag_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut waiter = Box::pin(cache.get_async(&1, sec, |_| async { panic!("should wait") }));
+        // Not asking to be polled again until the load lands
+        assert!(waiter.as_mut().poll(&mut cx).is_pending());
+        assert!(!woken.load(SeqCst));
+        release.wait();
+        assert_eq!(leader.join().unwrap(), Some(10));
+        assert!(woken.load(SeqCst));
+        assert_eq!(waiter.as_mut().poll(&mut cx), Poll::Ready(Some(10)));
+    }
+
+    #[test]
+    fn sync_miss_on_async_load() {
+        // Blocking on the future would hang the only thread polling it, the get loads by itself
+        let cache = TTLCache::<usize, usize, _>::with_clock(16, ManualClock::new());
+        let sec = Duration::from_secs(1);
+        let gets = vec![
+            Box::pin(cache.get_async(&1, sec, |k: &usize| {
+                let k = *k;
+                async move {
+                    YieldNow(false).await;
+                    Some(k * 10)
+                }
+            })) as Pin<Box<dyn Future<Output = _>>>,
+            Box::pin(async { cache.get(&1, sec, |k| Some(k * 20)) }),
+        ];
+        assert_eq!(run_local(gets), vec![Some(10usize), Some(20)]);
+        assert_eq!(cache.get(&1, sec, |_| None), Some(10));
+        assert_eq!(cache.metrics().loads, 2);
+    }
+
+    #[test]
+    fn dump_and_restore() {
+        let cache = TTLCache::<usize, String, _>::with_clock(16, ManualClock::new());
+        cache.clock().set(Duration::from_secs(100));
+        for i in 1..=4 {
+            cache.get(&i, Duration::from_secs(i as u64 * 10), |k| {
+                Some(k.to_string())
+            });
+        }
+        cache.get(&5, Duration::from_secs(30), |_| None);
+        cache.clock().advance(Duration::from_secs(15));
+        let mut buf = vec![];
+        // Key 1 is already expired
+        assert_eq!(cache.dump(&mut buf).unwrap(), 4);
+        // Another clock with a different origin, only the remaining lifetimes matter
+        let restored = TTLCache::<usize, String, _>::with_clock(16, ManualClock::new());
+        assert_eq!(restored.restore_from(&mut &buf[..]).unwrap(), 4);
+        assert_eq!(restored.len(), 4);
+        let never = |_: &usize| -> Option<String> { panic!("should be restored") };
+        assert_eq!(
+            restored.get(&2, Duration::from_secs(1), never),
+            Some("2".to_string())
+        );
+        assert_eq!(restored.get(&5, Duration::from_secs(1), never), None);
+        restored.clock().advance(Duration::from_secs(6));
+        assert_eq!(restored.get(&2, Duration::from_secs(1), |_| None), None);
+        assert_eq!(
+            restored.get(&4, Duration::from_secs(1), never),
+            Some("4".to_string())
+        );
+        let monotonic = TTLCache::<usize, String>::restore(16, &mut &buf[..]).unwrap();
+        assert_eq!(monotonic.len(), 4);
+        let positive = TTLCache::<usize, String, _>::with_clock(16, ManualClock::new())
+            .with_negative_caching(NegativeCaching::Disabled);
+        assert_eq!(positive.restore_from(&mut &buf[..]).unwrap(), 3);
+        assert_eq!(positive.len(), 3);
+        assert!(TTLCache::<usize, String>::restore(16, &mut &buf[..10]).is_err());
     }
 }